@@ -0,0 +1,79 @@
+#version 330 core
+
+uniform sampler2D frame;
+// 0 for NTSC, 1 for PAL
+uniform int standard;
+// frames drawn, the subcarrier phase of a line moves with it
+uniform int frameCount;
+
+in vec2 uv;
+
+const float PI = 3.14159265;
+
+// subcarrier cycles across the visible part of a line
+const float NTSC_CYCLES = 188.0;
+const float PAL_CYCLES = 230.0;
+
+// the signal is sampled at four times the subcarrier frequency, this many
+// samples to each side, so chroma is filtered over six cycles
+const int TAPS = 12;
+
+// column-major, luma and the two chroma components from RGB and back
+const mat3 RGB_TO_YIQ = mat3(0.299, 0.596, 0.211, 0.587, -0.274, -0.523, 0.114, -0.322, 0.312);
+const mat3 YIQ_TO_RGB = mat3(1.0, 1.0, 1.0, 0.956, -0.272, -1.106, 0.621, -0.647, 1.703);
+const mat3 RGB_TO_YUV = mat3(0.299, -0.147, 0.615, 0.587, -0.289, -0.515, 0.114, 0.436, -0.100);
+const mat3 YUV_TO_RGB = mat3(1.0, 1.0, 1.0, 0.0, -0.395, 2.032, 1.140, -0.581, 0.0);
+
+// Encodes texture row `row` as a composite signal around `x`, in subcarrier
+// cycles from the left, and decodes it back into luma and chroma. Luma is
+// averaged over one cycle, which notches out the subcarrier, chroma is
+// demodulated and low-passed over the whole window. Sharp luma edges leak
+// into chroma as artifact colours, and chroma bleeds over several cycles.
+vec3 decode(float x, int row, float cycles, mat3 toSignal) {
+    vec2 size = vec2(textureSize(frame, 0));
+    float v = (float(row) + 0.5) / size.y;
+
+    // NTSC shifts the subcarrier by half a cycle every line and frame, PAL
+    // by three quarters and also flips the phase of V on every other line
+    float phase;
+    float vSwitch = 1.0;
+    if (standard == 0) {
+        phase = PI * float(row + frameCount);
+    } else {
+        phase = 1.5 * PI * float(row) + 0.5 * PI * float(frameCount);
+        vSwitch = row % 2 == 0 ? 1.0 : -1.0;
+    }
+
+    vec3 sum = vec3(0.0);
+    vec3 weights = vec3(0.0);
+    for (int k = -TAPS; k <= TAPS; k++) {
+        float t = x + float(k) * 0.25;
+        vec3 yc = toSignal * texture(frame, vec2(t / cycles, v)).rgb;
+        float angle = 2.0 * PI * t + phase;
+        float signal = yc.x + yc.y * cos(angle) + vSwitch * yc.z * sin(angle);
+
+        float luma = abs(k) < 2 ? 1.0 : abs(k) == 2 ? 0.5 : 0.0;
+        float chroma = 1.0 + cos(PI * float(k) / float(TAPS + 1));
+        sum += vec3(luma, 2.0 * chroma * cos(angle), 2.0 * chroma * vSwitch * sin(angle)) * signal;
+        weights += vec3(luma, chroma, chroma);
+    }
+    return sum / weights;
+}
+
+void main() {
+    int row = int(uv.y * float(textureSize(frame, 0).y));
+
+    vec3 rgb;
+    if (standard == 0) {
+        rgb = YIQ_TO_RGB * decode(uv.x * NTSC_CYCLES, row, NTSC_CYCLES, RGB_TO_YIQ);
+    } else {
+        // the delay line of the decoder averages chroma with the line above,
+        // cancelling phase errors at the cost of vertical colour resolution
+        vec3 yuv = decode(uv.x * PAL_CYCLES, row, PAL_CYCLES, RGB_TO_YUV);
+        vec3 above = decode(uv.x * PAL_CYCLES, row + 1, PAL_CYCLES, RGB_TO_YUV);
+        yuv.yz = (yuv.yz + above.yz) * 0.5;
+        rgb = YUV_TO_RGB * yuv;
+    }
+
+    gl_FragColor = vec4(clamp(rgb, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,567 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use glow::HasContext;
+
+use crate::{
+    default_tab_stops,
+    parser::{Action, Csi, Esc},
+    CRTTerm, ClipboardPolicy, CursorShape, CursorStyle, TermEvent, MAX_PENDING_EVENTS,
+    MAX_PENDING_RESPONSES, TITLE_STACK_LEN,
+};
+
+/// Terminal modes switched by the child process.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Modes {
+    /// DECCKM: cursor keys send SS3 instead of CSI sequences.
+    pub(crate) app_cursor: bool,
+    /// DECAWM: printing past the last column continues on the next line.
+    pub(crate) autowrap: bool,
+    /// DECTCEM: the cursor is shown.
+    pub(crate) cursor_visible: bool,
+    /// xterm bracketed paste, pasted text is wrapped in `ESC[200~`/`ESC[201~`.
+    pub(crate) bracketed_paste: bool,
+    pub(crate) mouse_tracking: MouseTracking,
+    pub(crate) mouse_encoding: MouseEncoding,
+}
+
+/// Which mouse events are reported to the application, each mode reporting
+/// everything the ones before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub(crate) enum MouseTracking {
+    #[default]
+    Off,
+    /// Mode 9, button presses only.
+    X10,
+    /// Mode 1000, presses and releases.
+    Normal,
+    /// Mode 1002, also motion while a button is held.
+    ButtonEvent,
+    /// Mode 1003, all motion.
+    AnyEvent,
+}
+
+/// How mouse reports are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum MouseEncoding {
+    /// `ESC[M` followed by three bytes offset by 32, limited to 223 columns.
+    #[default]
+    X10,
+    /// Mode 1006, `ESC[<b;x;yM` with `m` for releases.
+    Sgr,
+    /// Mode 1015, `ESC[b;x;yM` with decimal numbers.
+    Urxvt,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            app_cursor: false,
+            autowrap: true,
+            cursor_visible: true,
+            bracketed_paste: false,
+            mouse_tracking: Default::default(),
+            mouse_encoding: Default::default(),
+        }
+    }
+}
+
+impl<C: HasContext> CRTTerm<C> {
+    pub(crate) fn perform(&mut self, action: Action) {
+        match action {
+            Action::Print(c) => self.print(c),
+            Action::Execute(byte) => self.execute(byte),
+            Action::Esc(esc) => self.esc_dispatch(esc),
+            Action::Csi(csi) => self.csi_dispatch(csi),
+            Action::Osc(payload) => self.osc_dispatch(&payload),
+        }
+    }
+
+    fn osc_dispatch(&mut self, payload: &[u8]) {
+        let (command, data) = match payload.iter().position(|&b| b == b';') {
+            Some(i) => (&payload[..i], &payload[i + 1..]),
+            None => (payload, &[][..]),
+        };
+
+        match command {
+            b"0" | b"1" | b"2" => {
+                let text: String = String::from_utf8_lossy(data)
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .collect();
+                if command != b"2" {
+                    self.set_icon_name(text.clone());
+                }
+                if command != b"1" {
+                    self.set_title(text);
+                }
+            }
+            // hyperlink, `8;params;URI`
+            b"8" => {
+                let mut parts = data.splitn(2, |&b| b == b';');
+                let params = parts.next().unwrap_or_default();
+                self.set_hyperlink(params, parts.next().unwrap_or_default());
+            }
+            // clipboard, the selection targets before the data are ignored
+            b"52" => {
+                let Some(i) = data.iter().position(|&b| b == b';') else {
+                    return;
+                };
+                let policy = self.screen.clipboard_policy;
+                match &data[i + 1..] {
+                    b"?" if policy == ClipboardPolicy::ReadWrite => {
+                        self.push_event(TermEvent::QueryClipboard)
+                    }
+                    b"?" => {}
+                    _ if policy == ClipboardPolicy::Deny => {}
+                    data => {
+                        if let Some(text) = BASE64
+                            .decode(data)
+                            .ok()
+                            .and_then(|bytes| String::from_utf8(bytes).ok())
+                        {
+                            self.push_event(TermEvent::SetClipboard(text));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_title(&mut self, title: String) {
+        self.title = title.clone();
+        self.push_event(TermEvent::Title(title));
+    }
+
+    fn set_icon_name(&mut self, icon_name: String) {
+        self.icon_name = icon_name.clone();
+        self.push_event(TermEvent::IconName(icon_name));
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // BS
+            0x08 => self.goto(self.cursor[0].saturating_sub(1), self.cursor[1]),
+            // HT
+            0x09 => self.tab(1),
+            // LF, VT, FF
+            0x0a..=0x0c => self.line_feed(),
+            // CR
+            0x0d => self.goto(0, self.cursor[1]),
+            _ => {}
+        }
+    }
+
+    /// Moves the cursor `count` tab stops forward, or backward if negative,
+    /// stopping at the edges of the line.
+    fn tab(&mut self, count: isize) {
+        let last = self.screen.chars_size[0].saturating_sub(1);
+        let mut x = self.cursor[0];
+        for _ in 0..count.unsigned_abs() {
+            x = if count > 0 {
+                (x + 1..last).find(|&x| self.tab_stops[x]).unwrap_or(last)
+            } else {
+                (0..x).rev().find(|&x| self.tab_stops[x]).unwrap_or(0)
+            };
+        }
+        self.goto(x, self.cursor[1]);
+    }
+
+    fn esc_dispatch(&mut self, esc: Esc) {
+        if !esc.intermediates.is_empty() {
+            return;
+        }
+
+        match esc.final_byte {
+            // DECSC, DECRC
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            // IND
+            b'D' => self.line_feed(),
+            // NEL
+            b'E' => {
+                self.cursor[0] = 0;
+                self.line_feed();
+            }
+            // RI
+            b'M' => self.reverse_line_feed(),
+            // HTS
+            b'H' => {
+                if let Some(stop) = self.tab_stops.get_mut(self.cursor[0]) {
+                    *stop = true;
+                }
+            }
+            // RIS
+            b'c' => {
+                self.set_alt_screen(false);
+                self.modes = Default::default();
+                self.cursor_style = None;
+                self.active_link = 0;
+                self.scroll_region = 0..self.screen.chars_size[1];
+                self.tab_stops = default_tab_stops(self.screen.chars_size[0]);
+                self.erase_rows(0..self.screen.chars_size[1]);
+                self.goto(0, 0);
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, csi: Csi) {
+        // DECSCUSR
+        if csi.private.is_none() && csi.intermediates == b" " && csi.final_byte == b'q' {
+            let shape = match csi.param(0, 1) {
+                1 | 2 => CursorShape::Block,
+                3 | 4 => CursorShape::Underline,
+                _ => CursorShape::Bar,
+            };
+            self.cursor_style = csi.params.first().filter(|&&p| p != 0).map(|&p| CursorStyle {
+                shape,
+                blinking: p % 2 == 1,
+            });
+            return;
+        }
+
+        if !csi.intermediates.is_empty() {
+            return;
+        }
+
+        match (csi.private, csi.final_byte) {
+            (Some(b'?'), b'h' | b'l') => {
+                let enable = csi.final_byte == b'h';
+                for &mode in &csi.params {
+                    self.set_private_mode(mode, enable);
+                }
+            }
+            // DA2, reporting a VT220
+            (Some(b'>'), b'c') if csi.param(0, 0) == 0 => self.respond("\x1b[>1;10;0c"),
+            // DECXCPR
+            (Some(b'?'), b'n') if csi.param(0, 0) == 6 => {
+                let [x, y] = self.cursor;
+                self.respond(&format!("\x1b[?{};{}R", y + 1, x + 1));
+            }
+            (Some(_), _) => {}
+
+            // DA1, reporting a VT220
+            (None, b'c') if csi.param(0, 0) == 0 => self.respond("\x1b[?62c"),
+            // DSR
+            (None, b'n') => match csi.param(0, 0) {
+                5 => self.respond("\x1b[0n"),
+                6 => {
+                    let [x, y] = self.cursor;
+                    self.respond(&format!("\x1b[{};{}R", y + 1, x + 1));
+                }
+                _ => {}
+            },
+            // XTWINOPS
+            (None, b't') => {
+                let [cols, rows] = self.screen.chars_size;
+                let [width, height] = self.screen.frame_size;
+                match csi.param(0, 0) {
+                    // push and pop the icon name (1), title (2) or both (0)
+                    22 => {
+                        if self.title_stack.len() == TITLE_STACK_LEN {
+                            self.title_stack.remove(0);
+                        }
+                        self.title_stack.push((self.icon_name.clone(), self.title.clone()));
+                    }
+                    23 => {
+                        if let Some((icon_name, title)) = self.title_stack.pop() {
+                            let which = csi.params.get(1).copied().unwrap_or(0);
+                            if which != 2 {
+                                self.set_icon_name(icon_name);
+                            }
+                            if which != 1 {
+                                self.set_title(title);
+                            }
+                        }
+                    }
+                    14 => self.respond(&format!("\x1b[4;{height};{width}t")),
+                    16 => {
+                        let cell_width = width as usize / cols.max(1);
+                        let cell_height = height as usize / rows.max(1);
+                        self.respond(&format!("\x1b[6;{cell_height};{cell_width}t"));
+                    }
+                    18 => self.respond(&format!("\x1b[8;{rows};{cols}t")),
+                    19 => self.respond(&format!("\x1b[9;{rows};{cols}t")),
+                    _ => {}
+                }
+            }
+
+            // SCOSC, SCORC
+            (None, b's') if csi.params.is_empty() => self.save_cursor(),
+            (None, b'u') if csi.params.is_empty() => self.restore_cursor(),
+
+            (None, b'A') => self.move_cursor(0, -(csi.param(0, 1) as isize)),
+            (None, b'B') => self.move_cursor(0, csi.param(0, 1) as isize),
+            (None, b'C') => self.move_cursor(csi.param(0, 1) as isize, 0),
+            (None, b'D') => self.move_cursor(-(csi.param(0, 1) as isize), 0),
+            // CNL, CPL
+            (None, b'E') => {
+                self.move_cursor(0, csi.param(0, 1) as isize);
+                self.cursor[0] = 0;
+            }
+            (None, b'F') => {
+                self.move_cursor(0, -(csi.param(0, 1) as isize));
+                self.cursor[0] = 0;
+            }
+            // CHA
+            (None, b'G') => self.goto(csi.param(0, 1) as usize - 1, self.cursor[1]),
+            // CUP, HVP
+            (None, b'H' | b'f') => {
+                self.goto(csi.param(1, 1) as usize - 1, csi.param(0, 1) as usize - 1)
+            }
+            // VPA
+            (None, b'd') => self.goto(self.cursor[0], csi.param(0, 1) as usize - 1),
+            // ED
+            (None, b'J') => {
+                let [cols, rows] = self.screen.chars_size;
+                let [x, y] = self.cursor;
+                match csi.param(0, 0) {
+                    0 => {
+                        self.erase_cells(y, x..cols);
+                        self.erase_rows(y + 1..rows);
+                    }
+                    1 => {
+                        self.erase_rows(0..y);
+                        self.erase_cells(y, 0..x + 1);
+                    }
+                    2 => self.erase_rows(0..rows),
+                    3 => {
+                        self.scrollback.clear();
+                        self.scrollback_links.clear();
+                        self.scrollback_wrapped.clear();
+                        self.view_offset = 0;
+                        self.selection = None;
+                        self.dirty_rows.fill(true);
+                    }
+                    _ => {}
+                }
+            }
+            // CHT, CBT
+            (None, b'I') => self.tab(csi.param(0, 1) as isize),
+            (None, b'Z') => self.tab(-(csi.param(0, 1) as isize)),
+            // TBC
+            (None, b'g') => match csi.param(0, 0) {
+                0 => {
+                    if let Some(stop) = self.tab_stops.get_mut(self.cursor[0]) {
+                        *stop = false;
+                    }
+                }
+                3 => self.tab_stops.fill(false),
+                _ => {}
+            },
+            // DECSTBM
+            (None, b'r') => {
+                let rows = self.screen.chars_size[1];
+                let top = csi.param(0, 1) as usize - 1;
+                let bottom = (csi.param(1, rows as u16) as usize).min(rows);
+                if top + 1 < bottom {
+                    self.scroll_region = top..bottom;
+                    self.goto(0, 0);
+                }
+            }
+            // SU, SD
+            (None, b'S') => {
+                self.scroll_up(self.scroll_region.clone(), csi.param(0, 1) as usize, true)
+            }
+            (None, b'T') if csi.params.len() <= 1 => {
+                self.scroll_down(self.scroll_region.clone(), csi.param(0, 1) as usize)
+            }
+            // IL, DL
+            (None, b'L' | b'M') => {
+                let y = self.cursor[1];
+                if self.scroll_region.contains(&y) {
+                    let rows = y..self.scroll_region.end;
+                    let count = csi.param(0, 1) as usize;
+                    if csi.final_byte == b'L' {
+                        self.scroll_down(rows, count);
+                    } else {
+                        self.scroll_up(rows, count, false);
+                    }
+                    self.goto(0, y);
+                }
+            }
+            // ICH, DCH, ECH
+            (None, b'@' | b'P' | b'X') => {
+                let [x, y] = self.cursor;
+                let count = csi.param(0, 1) as usize;
+                if y < self.chars.len() {
+                    edit_cells(&mut self.chars[y], x, count, csi.final_byte);
+                    edit_cells(&mut self.links[y], x, count, csi.final_byte);
+                    self.mark_dirty(y..y + 1);
+                }
+            }
+            // EL
+            (None, b'K') => {
+                let cols = self.screen.chars_size[0];
+                let [x, y] = self.cursor;
+                match csi.param(0, 0) {
+                    0 => self.erase_cells(y, x..cols),
+                    1 => self.erase_cells(y, 0..x + 1),
+                    2 => self.erase_cells(y, 0..cols),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn respond(&mut self, response: &str) {
+        // a partial reply would garble the input of the child
+        if self.responses.len() + response.len() <= MAX_PENDING_RESPONSES {
+            self.responses.extend_from_slice(response.as_bytes());
+        }
+    }
+
+    pub(crate) fn push_event(&mut self, event: TermEvent) {
+        if self.events.len() == MAX_PENDING_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enable: bool) {
+        let tracking = match mode {
+            9 => Some(MouseTracking::X10),
+            1000 => Some(MouseTracking::Normal),
+            1002 => Some(MouseTracking::ButtonEvent),
+            1003 => Some(MouseTracking::AnyEvent),
+            _ => None,
+        };
+        if let Some(tracking) = tracking {
+            if enable {
+                self.modes.mouse_tracking = tracking;
+            } else if self.modes.mouse_tracking == tracking {
+                self.modes.mouse_tracking = MouseTracking::Off;
+            }
+            return;
+        }
+
+        let encoding = match mode {
+            1006 => Some(MouseEncoding::Sgr),
+            1015 => Some(MouseEncoding::Urxvt),
+            _ => None,
+        };
+        if let Some(encoding) = encoding {
+            if enable {
+                self.modes.mouse_encoding = encoding;
+            } else if self.modes.mouse_encoding == encoding {
+                self.modes.mouse_encoding = MouseEncoding::X10;
+            }
+            return;
+        }
+
+        match mode {
+            1 => self.modes.app_cursor = enable,
+            7 => self.modes.autowrap = enable,
+            12 => {
+                let style = self.cursor_style.unwrap_or(self.screen.cursor_style);
+                self.cursor_style = Some(CursorStyle {
+                    blinking: enable,
+                    ..style
+                });
+            }
+            25 => self.modes.cursor_visible = enable,
+            47 => self.set_alt_screen(enable),
+            1047 => {
+                if !enable && self.alt_screen {
+                    self.erase_rows(0..self.screen.chars_size[1]);
+                }
+                self.set_alt_screen(enable);
+            }
+            1048 if enable => self.save_cursor(),
+            1048 => self.restore_cursor(),
+            1049 if enable && !self.alt_screen => {
+                self.save_cursor();
+                self.set_alt_screen(true);
+                self.erase_rows(0..self.screen.chars_size[1]);
+            }
+            1049 if !enable && self.alt_screen => {
+                self.set_alt_screen(false);
+                self.restore_cursor();
+            }
+            2004 => self.modes.bracketed_paste = enable,
+            _ => {}
+        }
+    }
+
+    /// Swaps the primary and alternate screen contents. The view jumps back
+    /// to the bottom and the selection is dropped, since both refer to the
+    /// screen being left.
+    fn set_alt_screen(&mut self, enable: bool) {
+        if self.alt_screen == enable {
+            return;
+        }
+
+        std::mem::swap(&mut self.chars, &mut self.other_chars);
+        std::mem::swap(&mut self.links, &mut self.other_links);
+        std::mem::swap(&mut self.wrapped, &mut self.other_wrapped);
+        self.alt_screen = enable;
+        self.view_offset = 0;
+        self.selection = None;
+        self.dirty_rows.fill(true);
+        if let Some(search) = &mut self.search {
+            search.mark_history_stale();
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursors[self.alt_screen as usize] = self.cursor;
+    }
+
+    fn restore_cursor(&mut self) {
+        let [x, y] = self.saved_cursors[self.alt_screen as usize];
+        self.goto(x, y);
+    }
+
+    /// Moves the cursor to `[x, y]`, clamped to the grid.
+    fn goto(&mut self, x: usize, y: usize) {
+        self.pending_wrap = false;
+        let [cols, rows] = self.screen.chars_size;
+        self.cursor = [x.min(cols.saturating_sub(1)), y.min(rows.saturating_sub(1))];
+    }
+
+    fn move_cursor(&mut self, dx: isize, dy: isize) {
+        self.goto(
+            self.cursor[0].saturating_add_signed(dx),
+            self.cursor[1].saturating_add_signed(dy),
+        );
+    }
+
+    fn erase_cells(&mut self, row: usize, cols: std::ops::Range<usize>) {
+        if let Some(line) = self.chars.get_mut(row) {
+            let end = cols.end.min(line.len());
+            line[cols.start.min(end)..end].fill('\0');
+            self.links[row][cols.start.min(end)..end].fill(0);
+            // the end of the row is gone, whatever was wrapped from it too
+            if end == line.len() {
+                self.wrapped[row] = false;
+            }
+            self.mark_dirty(row..row + 1);
+        }
+    }
+
+    fn erase_rows(&mut self, rows: std::ops::Range<usize>) {
+        for row in rows {
+            self.erase_cells(row, 0..self.screen.chars_size[0]);
+        }
+    }
+}
+
+/// Inserts (ICH), deletes (DCH) or erases (ECH) `count` cells of `row` at `x`.
+fn edit_cells<T: Copy + Default>(row: &mut [T], x: usize, count: usize, final_byte: u8) {
+    let x = x.min(row.len());
+    let cells = &mut row[x..];
+    let count = count.min(cells.len());
+    match final_byte {
+        b'@' => {
+            cells.rotate_right(count);
+            cells[..count].fill(T::default());
+        }
+        b'P' => {
+            cells.rotate_left(count);
+            let len = cells.len();
+            cells[len - count..].fill(T::default());
+        }
+        _ => cells[..count].fill(T::default()),
+    }
+}
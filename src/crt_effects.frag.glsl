@@ -1,11 +1,23 @@
 #version 330 core
 
 uniform sampler2D frame;
-uniform vec2 pixelSize;
-uniform float time;
 
-uniform vec3 bgColor;
-uniform vec3 fgColor;
+layout (std140) uniform EffectParams {
+    vec2 pixelSize;
+    float time;
+
+    vec3 bgColor;
+    vec3 fgColor;
+    float rollingScanline;
+    // 0 without a simulated raster
+    float rasterLines;
+    // 0 without a mask, otherwise MaskKind plus one
+    float maskKind;
+    // triad width in frame pixels
+    float maskPitch;
+    // 1 when the frame comes from the composite signal, with colour
+    float composite;
+};
 
 in vec2 uv;
 
@@ -35,7 +47,61 @@ vec3 clamp01(vec3 i) {
 const int BLUR_SIZE = 12;
 const float BLUR_QUALITY = 0.75;
 
+// beam width in lines, as the standard deviation of its profile, for dark
+// and fully lit lines
+const float BEAM_WIDTH_DARK = 0.18;
+const float BEAM_WIDTH_BRIGHT = 0.4;
+
+const float MASK_SLOT = 2.0;
+const float MASK_SHADOW = 3.0;
+// light let through between the phosphors
+const float MASK_DARK = 0.25;
+// lit part of the height of a slot
+const float SLOT_FILL = 0.8;
+
+// integral of a pattern lit for `fill` at the start of every period
+vec3 litIntegral(vec3 t, float fill) {
+    return floor(t) * fill + min(fract(t), fill);
+}
+
+// share of the pixel of width `size` centred at `t` that is lit, so the
+// pattern does not alias at any pitch
+vec3 coverage(vec3 t, float size, float fill) {
+    return (litIntegral(t + size * 0.5, fill) - litIntegral(t - size * 0.5, fill)) / size;
+}
+
+// brightness of the red, green and blue phosphors at `pos` in frame pixels,
+// made up so the mask does not darken the picture on average
+vec3 phosphors(vec2 pos) {
+    // in triads
+    vec2 p = pos / maskPitch;
+    float pixel = 1.0 / maskPitch;
+    float lit = 1.0 / 3.0;
+
+    if (maskKind == MASK_SHADOW) {
+        // rows half a triad tall
+        p.x += 0.5 * mod(floor(p.y * 2.0), 2.0);
+    }
+    vec3 w = coverage(p.x - vec3(0.0, 1.0, 2.0) / 3.0, pixel, 1.0 / 3.0);
+    if (maskKind == MASK_SLOT) {
+        // slots a triad tall
+        float y = p.y + 0.5 * mod(floor(p.x), 2.0);
+        w *= coverage(vec3(y), pixel, SLOT_FILL);
+        lit *= SLOT_FILL;
+    }
+
+    vec3 mask = mix(vec3(MASK_DARK), vec3(1.0), w);
+    return mask / (MASK_DARK + (1.0 - MASK_DARK) * lit);
+}
+
 void main() {
+    // with a raster every line is sampled at its centre, the beam shape
+    // across the line is applied below
+    float line = uv.y * rasterLines;
+    vec2 frameUv = uv;
+    if (rasterLines > 0) {
+        frameUv.y = (floor(line) + 0.5) / rasterLines;
+    }
 
     int mid = BLUR_SIZE / 2;
     vec3 color = vec3(0);
@@ -52,16 +118,27 @@ void main() {
             }
             float idist = clamp(1 - dist, 0.0, 1.0);
 
-            vec2 new_uv = uv + pixelSize * diff / BLUR_QUALITY * rand_mod;
+            vec2 new_uv = frameUv + pixelSize * diff / BLUR_QUALITY * rand_mod;
             color += sample(new_uv).rgb * idist * rand_mod * 0.2;
         }
     }
     color /= BLUR_SIZE;
 
-    color = max(color, clamp01(color + sample(uv).rgb));
+    color = max(color, clamp01(color + sample(frameUv).rgb));
 
     float light = (color.r + color.g + color.b) / 3;
-    color = lerp(bgColor, fgColor, vec3(light));
+    // colour of the decoded composite signal, shown over the phosphor tint
+    vec3 chroma = (color - light) * composite;
+
+    if (rasterLines > 0) {
+        // brighter lines bloom wider, the gain makes up for the dark gaps
+        float width = mix(BEAM_WIDTH_DARK, BEAM_WIDTH_BRIGHT, light);
+        float offset = fract(line) - 0.5;
+        float beam = exp(-0.5 * offset * offset / (width * width));
+        light = clamp(light * beam * 1.5, 0.0, 1.0);
+    }
+
+    color = clamp01(lerp(bgColor, fgColor, vec3(light)) + chroma);
 
     float scanline = 1 - fract(mod(time, 5.0) / 5 + uv.y);
     float scanline_start = 0.75;
@@ -71,5 +148,11 @@ void main() {
         scanline = -cos(1.57079 * ((scanline - scanline_start) / (1 - scanline_start))) + 1;
     }
 
+    scanline *= rollingScanline;
+
+    if (maskKind > 0) {
+        color *= phosphors(uv / pixelSize);
+    }
+
     gl_FragColor = vec4(color * (1 + scanline * 0.3) * rand_mod, 1);
 }
\ No newline at end of file
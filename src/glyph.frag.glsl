@@ -0,0 +1,10 @@
+
+in vec2 cellPos;
+flat in uvec4 cell;
+
+void main() {
+    if (!inField()) {
+        discard;
+    }
+    gl_FragColor = shadeCell(cell.x, cell.y, decodeColor(cell), cellPos);
+}
@@ -0,0 +1,28 @@
+
+layout (location = 0) in vec2 corner;
+layout (location = 1) in uvec4 cell_in;
+
+uniform vec2 gridOrigin;
+uniform vec2 cellSize;
+uniform uint columns;
+
+out vec2 cellPos;
+flat out uvec4 cell;
+
+void main() {
+    cell = cell_in;
+
+    // empty cells collapse into a degenerate quad
+    if (cell.x == 0u && cell.y == 0u) {
+        gl_Position = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    uint index = uint(gl_InstanceID);
+    vec2 grid = vec2(index % columns, index / columns);
+
+    vec2 pos = gridOrigin + vec2(grid.x + corner.x, -(grid.y + 1.0 - corner.y)) * cellSize;
+    gl_Position = vec4(pos, 0.0, 1.0);
+
+    cellPos = vec2(corner.x, 1.0 - corner.y) * FONT_CELL_SIZE;
+}
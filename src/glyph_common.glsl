@@ -0,0 +1,50 @@
+
+uniform sampler2D font;
+// lines drawn this frame when interlaced, 0 or 1, or -1 for all lines
+uniform int field;
+
+// whether the fragment is on a line of the field being drawn
+bool inField() {
+    return field < 0 || int(gl_FragCoord.y) % 2 == field;
+}
+
+vec4 decodeColor(uvec4 cell) {
+    return vec4(
+        float(cell.z & 0xffu),
+        float(cell.z >> 8),
+        float(cell.w & 0xffu),
+        float(cell.w >> 8)
+    ) / 255.0;
+}
+
+// cellPos is the position inside the cell in font pixels, top to bottom
+vec4 shadeCell(uint glyph, uint flags, vec4 color, vec2 cellPos) {
+    ivec2 pixel = min(ivec2(cellPos), ivec2(FONT_CELL_SIZE) - 1);
+
+    vec4 texel = vec4(0.0);
+    if (pixel.x < FONT_CHAR_SIZE.x && pixel.y < FONT_CHAR_SIZE.y) {
+        ivec2 atlas = ivec2(glyph % FONT_COLS, glyph / FONT_COLS) * FONT_IMAGE_CELL_SIZE;
+        texel = texelFetch(font, atlas + pixel, 0);
+    }
+
+    // selected cells and the current search match are drawn inverted, lit
+    // everywhere but the glyph, other matches are lined in the spacing below.
+    // Partial brightness is no use here, the phosphor builds it up to full.
+    bool selected = (flags & (CELL_SELECTED | CELL_CURRENT_MATCH)) != 0u;
+    float ink = selected ? 1.0 - texel.a : texel.a;
+    if (!selected && (flags & CELL_MATCH) != 0u && pixel.y >= FONT_CHAR_SIZE.y) {
+        ink = 1.0;
+    }
+
+    // a block cursor inverts the cell again, underlines and thin cursors
+    // are drawn over it
+    bool underline = (flags & (CELL_CURSOR_UNDERLINE | CELL_UNDERLINE)) != 0u;
+    if ((flags & CELL_CURSOR_BLOCK) != 0u) {
+        ink = 1.0 - ink;
+    } else if ((underline && pixel.y >= FONT_CHAR_SIZE.y - 1)
+        || ((flags & CELL_CURSOR_BAR) != 0u && pixel.x == 0)) {
+        ink = selected ? 0.0 : 1.0;
+    }
+
+    return vec4(color.rgb, color.a * ink);
+}
@@ -0,0 +1,23 @@
+
+uniform usampler2D cells;
+uniform vec2 gridOrigin;
+uniform vec2 cellSize;
+
+in vec2 uv;
+
+void main() {
+    if (!inField()) {
+        discard;
+    }
+
+    vec2 pos = uv * 2.0 - 1.0;
+    vec2 grid = vec2(pos.x - gridOrigin.x, gridOrigin.y - pos.y) / cellSize;
+
+    ivec2 index = ivec2(floor(grid));
+    if (any(lessThan(index, ivec2(0))) || any(greaterThanEqual(index, textureSize(cells, 0)))) {
+        discard;
+    }
+
+    uvec4 cell = texelFetch(cells, index, 0);
+    gl_FragColor = shadeCell(cell.x, cell.y, decodeColor(cell), fract(grid) * FONT_CELL_SIZE);
+}
@@ -0,0 +1,453 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use glow::HasContext;
+
+use crate::{
+    control::{Modes, MouseEncoding, MouseTracking},
+    CRTTerm,
+};
+
+/// Keys that send something other than their text to the child process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function key `F1` to `F12`.
+    F(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEvent {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// The pointer entered another cell, with the button held down if any.
+    Motion(Option<MouseButton>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    /// xterm's modifier parameter, `1` when no modifier is held.
+    fn param(self) -> u8 {
+        1 + self.shift as u8 + self.alt as u8 * 2 + self.ctrl as u8 * 4
+    }
+
+    fn any(self) -> bool {
+        self.shift || self.alt || self.ctrl
+    }
+}
+
+impl<C: HasContext> CRTTerm<C> {
+    /// Bytes to send to the child process when `key` is pressed.
+    pub fn key_input(&self, key: Key, mods: Modifiers) -> Vec<u8> {
+        key_bytes(&self.modes, key, mods)
+    }
+
+    /// Bytes to send to the child process for typed text. Ctrl turns letters
+    /// into control characters and Alt prefixes them with `ESC`.
+    pub fn char_input(&self, c: char, mods: Modifiers) -> Vec<u8> {
+        char_bytes(c, mods)
+    }
+
+    /// Whether the application asked for mouse events. The host should then
+    /// send them through [`CRTTerm::mouse_input`] instead of selecting text.
+    pub fn mouse_reporting(&self) -> bool {
+        self.modes.mouse_tracking != MouseTracking::Off
+    }
+
+    /// Bytes reporting `event` over `cell` to the application, in the encoding
+    /// it asked for. Empty if the current tracking mode does not report the
+    /// event, or if the position cannot be encoded.
+    pub fn mouse_input(&self, event: MouseEvent, cell: [usize; 2], mods: Modifiers) -> Vec<u8> {
+        mouse_bytes(&self.modes, event, cell, mods)
+    }
+
+    /// Bytes to send to the child process for pasted `text`.
+    ///
+    /// With bracketed paste enabled the text is wrapped in `ESC[200~` and
+    /// `ESC[201~` so the application can tell it from typing, and C0 controls
+    /// other than tabs and line breaks are removed. Otherwise all control
+    /// characters are removed, so that pasting can only insert text and line
+    /// breaks.
+    pub fn paste_input(&self, text: &str) -> Vec<u8> {
+        paste_bytes(&self.modes, text)
+    }
+
+    /// Bytes answering [`TermEvent::QueryClipboard`](crate::TermEvent::QueryClipboard)
+    /// with the clipboard contents `text`.
+    pub fn clipboard_input(&self, text: &str) -> Vec<u8> {
+        format!("\x1b]52;c;{}\x07", BASE64.encode(text)).into_bytes()
+    }
+}
+
+fn key_bytes(modes: &Modes, key: Key, mods: Modifiers) -> Vec<u8> {
+    let alt_prefix = |bytes: &[u8]| {
+        if mods.alt {
+            [b"\x1b", bytes].concat()
+        } else {
+            bytes.to_vec()
+        }
+    };
+
+    let cursor_key = |c: char| {
+        if mods.any() {
+            format!("\x1b[1;{}{c}", mods.param()).into_bytes()
+        } else if modes.app_cursor {
+            format!("\x1bO{c}").into_bytes()
+        } else {
+            format!("\x1b[{c}").into_bytes()
+        }
+    };
+
+    let tilde_key = |n: u8| {
+        if mods.any() {
+            format!("\x1b[{n};{}~", mods.param()).into_bytes()
+        } else {
+            format!("\x1b[{n}~").into_bytes()
+        }
+    };
+
+    match key {
+        Key::Enter => alt_prefix(b"\r"),
+        Key::Backspace if mods.ctrl => alt_prefix(b"\x08"),
+        Key::Backspace => alt_prefix(b"\x7f"),
+        Key::Tab if mods.shift => b"\x1b[Z".to_vec(),
+        Key::Tab => alt_prefix(b"\t"),
+        Key::Escape => alt_prefix(b"\x1b"),
+        Key::Up => cursor_key('A'),
+        Key::Down => cursor_key('B'),
+        Key::Right => cursor_key('C'),
+        Key::Left => cursor_key('D'),
+        Key::Home => cursor_key('H'),
+        Key::End => cursor_key('F'),
+        Key::Insert => tilde_key(2),
+        Key::Delete => tilde_key(3),
+        Key::PageUp => tilde_key(5),
+        Key::PageDown => tilde_key(6),
+        Key::F(n @ 1..=4) => {
+            let c = (b'P' + n - 1) as char;
+            if mods.any() {
+                format!("\x1b[1;{}{c}", mods.param()).into_bytes()
+            } else {
+                format!("\x1bO{c}").into_bytes()
+            }
+        }
+        Key::F(n @ 5..=12) => tilde_key([15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5]),
+        Key::F(_) => Vec::new(),
+    }
+}
+
+fn char_bytes(c: char, mods: Modifiers) -> Vec<u8> {
+    let c = if mods.ctrl {
+        match c {
+            ' ' => '\0',
+            '?' => '\x7f',
+            '@'..='_' | 'a'..='z' => ((c.to_ascii_uppercase() as u8) & 0x1f) as char,
+            _ => c,
+        }
+    } else {
+        c
+    };
+
+    let mut bytes = Vec::with_capacity(5);
+    if mods.alt {
+        bytes.push(0x1b);
+    }
+    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    bytes
+}
+
+fn mouse_bytes(modes: &Modes, event: MouseEvent, cell: [usize; 2], mods: Modifiers) -> Vec<u8> {
+    let tracking = modes.mouse_tracking;
+    let reported = match event {
+        MouseEvent::Press(_) => tracking != MouseTracking::Off,
+        MouseEvent::Release(MouseButton::WheelUp | MouseButton::WheelDown) => false,
+        MouseEvent::Release(_) => tracking > MouseTracking::X10,
+        MouseEvent::Motion(Some(_)) => tracking >= MouseTracking::ButtonEvent,
+        MouseEvent::Motion(None) => tracking == MouseTracking::AnyEvent,
+    };
+    if !reported {
+        return Vec::new();
+    }
+
+    let button_code = |button| match button {
+        Some(MouseButton::Left) => 0,
+        Some(MouseButton::Middle) => 1,
+        Some(MouseButton::Right) => 2,
+        Some(MouseButton::WheelUp) => 64,
+        Some(MouseButton::WheelDown) => 65,
+        None => 3,
+    };
+    let mut code = match event {
+        MouseEvent::Press(button) => button_code(Some(button)),
+        // only SGR can tell which button was released
+        MouseEvent::Release(button) if modes.mouse_encoding == MouseEncoding::Sgr => {
+            button_code(Some(button))
+        }
+        MouseEvent::Release(_) => 3,
+        MouseEvent::Motion(button) => button_code(button) + 32,
+    };
+    if tracking != MouseTracking::X10 {
+        code += mods.shift as u32 * 4 + mods.alt as u32 * 8 + mods.ctrl as u32 * 16;
+    }
+
+    let [x, y] = cell.map(|c| c + 1);
+    match modes.mouse_encoding {
+        MouseEncoding::X10 => {
+            let encode = |v: usize| u8::try_from(v + 32).ok();
+            match (encode(code as usize), encode(x), encode(y)) {
+                (Some(code), Some(x), Some(y)) => vec![0x1b, b'[', b'M', code, x, y],
+                _ => Vec::new(),
+            }
+        }
+        MouseEncoding::Sgr => {
+            let action = if matches!(event, MouseEvent::Release(_)) { 'm' } else { 'M' };
+            format!("\x1b[<{code};{x};{y}{action}").into_bytes()
+        }
+        MouseEncoding::Urxvt => format!("\x1b[{};{x};{y}M", code + 32).into_bytes(),
+    }
+}
+
+fn paste_bytes(modes: &Modes, text: &str) -> Vec<u8> {
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+
+    if modes.bracketed_paste {
+        // without ESC no end marker can form inside the text, however it is
+        // nested, so the rest cannot escape the bracket
+        let text: String = text
+            .chars()
+            .filter(|&c| c >= ' ' || c == '\t' || c == '\r')
+            .collect();
+        [b"\x1b[200~", text.as_bytes(), b"\x1b[201~"].concat()
+    } else {
+        text.chars()
+            .filter(|&c| !c.is_control() || c == '\t' || c == '\r')
+            .collect::<String>()
+            .into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mouse_modes(tracking: MouseTracking, encoding: MouseEncoding) -> Modes {
+        Modes {
+            mouse_tracking: tracking,
+            mouse_encoding: encoding,
+            ..Modes::default()
+        }
+    }
+
+    #[test]
+    fn sgr_mouse() {
+        let modes = mouse_modes(MouseTracking::AnyEvent, MouseEncoding::Sgr);
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        let cases: [(MouseEvent, Modifiers, &[u8]); 7] = [
+            (
+                MouseEvent::Press(MouseButton::Left),
+                Modifiers::default(),
+                b"\x1b[<0;3;2M",
+            ),
+            (
+                MouseEvent::Release(MouseButton::Right),
+                Modifiers::default(),
+                b"\x1b[<2;3;2m",
+            ),
+            (
+                MouseEvent::Press(MouseButton::Middle),
+                ctrl,
+                b"\x1b[<17;3;2M",
+            ),
+            (
+                MouseEvent::Motion(Some(MouseButton::Left)),
+                Modifiers::default(),
+                b"\x1b[<32;3;2M",
+            ),
+            (
+                MouseEvent::Motion(None),
+                Modifiers::default(),
+                b"\x1b[<35;3;2M",
+            ),
+            (
+                MouseEvent::Press(MouseButton::WheelDown),
+                Modifiers::default(),
+                b"\x1b[<65;3;2M",
+            ),
+            (
+                MouseEvent::Release(MouseButton::WheelUp),
+                Modifiers::default(),
+                b"",
+            ),
+        ];
+        for (event, mods, bytes) in cases {
+            assert_eq!(mouse_bytes(&modes, event, [2, 1], mods), bytes, "{event:?}");
+        }
+    }
+
+    #[test]
+    fn mouse_tracking_modes() {
+        let press = MouseEvent::Press(MouseButton::Left);
+        let release = MouseEvent::Release(MouseButton::Left);
+        let drag = MouseEvent::Motion(Some(MouseButton::Left));
+        let cases: [(MouseTracking, [bool; 3]); 5] = [
+            (MouseTracking::Off, [false, false, false]),
+            (MouseTracking::X10, [true, false, false]),
+            (MouseTracking::Normal, [true, true, false]),
+            (MouseTracking::ButtonEvent, [true, true, true]),
+            (MouseTracking::AnyEvent, [true, true, true]),
+        ];
+        for (tracking, reported) in cases {
+            let modes = mouse_modes(tracking, MouseEncoding::Sgr);
+            let reports = [press, release, drag]
+                .map(|event| !mouse_bytes(&modes, event, [0, 0], Modifiers::default()).is_empty());
+            assert_eq!(reports, reported, "{tracking:?}");
+        }
+    }
+
+    #[test]
+    fn x10_mouse() {
+        let modes = mouse_modes(MouseTracking::Normal, MouseEncoding::X10);
+        let press = MouseEvent::Press(MouseButton::Left);
+        let release = MouseEvent::Release(MouseButton::Right);
+        let none = Modifiers::default();
+        assert_eq!(mouse_bytes(&modes, press, [0, 0], none), b"\x1b[M !!");
+        assert_eq!(mouse_bytes(&modes, release, [1, 2], none), b"\x1b[M#\"#");
+        // coordinates end at 223, where the byte reaches 255
+        assert_eq!(mouse_bytes(&modes, press, [222, 0], none), b"\x1b[M \xff!");
+        assert_eq!(mouse_bytes(&modes, press, [223, 0], none), b"");
+        assert_eq!(mouse_bytes(&modes, press, [0, 300], none), b"");
+    }
+
+    #[test]
+    fn urxvt_mouse() {
+        let modes = mouse_modes(MouseTracking::Normal, MouseEncoding::Urxvt);
+        let press = MouseEvent::Press(MouseButton::Left);
+        assert_eq!(
+            mouse_bytes(&modes, press, [299, 0], Modifiers::default()),
+            b"\x1b[32;300;1M"
+        );
+    }
+
+    #[test]
+    fn keys() {
+        let app_cursor = Modes {
+            app_cursor: true,
+            ..Modes::default()
+        };
+        let none = Modifiers::default();
+        let shift = Modifiers {
+            shift: true,
+            ..none
+        };
+        let ctrl_alt = Modifiers {
+            ctrl: true,
+            alt: true,
+            ..none
+        };
+        let alt = Modifiers { alt: true, ..none };
+        let cases: [(&Modes, Key, Modifiers, &[u8]); 10] = [
+            (&Modes::default(), Key::Up, none, b"\x1b[A"),
+            (&app_cursor, Key::Up, none, b"\x1bOA"),
+            (&app_cursor, Key::Left, ctrl_alt, b"\x1b[1;7D"),
+            (&Modes::default(), Key::Delete, shift, b"\x1b[3;2~"),
+            (&Modes::default(), Key::F(1), none, b"\x1bOP"),
+            (&Modes::default(), Key::F(12), none, b"\x1b[24~"),
+            (&Modes::default(), Key::Tab, shift, b"\x1b[Z"),
+            (&Modes::default(), Key::Backspace, none, b"\x7f"),
+            (&Modes::default(), Key::Backspace, ctrl_alt, b"\x1b\x08"),
+            (&Modes::default(), Key::Enter, alt, b"\x1b\r"),
+        ];
+        for (modes, key, mods, bytes) in cases {
+            assert_eq!(key_bytes(modes, key, mods), bytes, "{key:?} {mods:?}");
+        }
+    }
+
+    #[test]
+    fn ctrl_and_alt_chars() {
+        let none = Modifiers::default();
+        let ctrl = Modifiers { ctrl: true, ..none };
+        let alt = Modifiers { alt: true, ..none };
+        let ctrl_alt = Modifiers {
+            ctrl: true,
+            alt: true,
+            ..none
+        };
+        let cases: [(char, Modifiers, &[u8]); 11] = [
+            ('c', ctrl, b"\x03"),
+            ('C', ctrl, b"\x03"),
+            ('[', ctrl, b"\x1b"),
+            ('\\', ctrl, b"\x1c"),
+            ('@', ctrl, b"\x00"),
+            ('_', ctrl, b"\x1f"),
+            (' ', ctrl, b"\x00"),
+            ('?', ctrl, b"\x7f"),
+            ('d', ctrl_alt, b"\x1b\x04"),
+            ('x', alt, b"\x1bx"),
+            ('\u{e9}', alt, "\x1b\u{e9}".as_bytes()),
+        ];
+        for (c, mods, bytes) in cases {
+            assert_eq!(char_bytes(c, mods), bytes, "{c:?} {mods:?}");
+        }
+    }
+
+    #[test]
+    fn paste() {
+        let bracketed = Modes {
+            bracketed_paste: true,
+            ..Modes::default()
+        };
+        assert_eq!(
+            paste_bytes(&bracketed, "a\r\nb\n\tc"),
+            b"\x1b[200~a\rb\r\tc\x1b[201~"
+        );
+        assert_eq!(
+            paste_bytes(&Modes::default(), "a\x1b[31m\nb\x07\u{85}"),
+            b"a[31m\rb"
+        );
+    }
+
+    #[test]
+    fn bracketed_paste_end_marker() {
+        let modes = Modes {
+            bracketed_paste: true,
+            ..Modes::default()
+        };
+        assert_eq!(
+            paste_bytes(&modes, "a\x1b[201~b"),
+            b"\x1b[200~a[201~b\x1b[201~"
+        );
+        assert_eq!(
+            paste_bytes(&modes, "a\x1b[20\x1b[201~1~; rm -rf ~"),
+            b"\x1b[200~a[20[201~1~; rm -rf ~\x1b[201~"
+        );
+    }
+}
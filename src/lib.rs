@@ -1,124 +1,389 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use glow::HasContext;
 
+mod control;
+mod input;
+mod link;
+mod parser;
+mod reflow;
+mod resources;
+mod search;
+mod selection;
+
+use control::Modes;
+pub use input::{Key, Modifiers, MouseButton, MouseEvent};
+pub use link::Link;
+use link::{Hover, Hyperlink};
+use parser::Parser;
+pub use resources::CrtResources;
+use resources::EFFECT_PARAMS_BINDING;
+use search::Search;
+pub use search::{SearchMatch, SearchOptions};
+use selection::{Point, Selection, SelectionRange};
+pub use selection::SelectionKind;
+
 #[derive(Debug, Clone, Copy)]
 pub struct ScreenInfo {
     pub gl_pos: [f32; 2],
     pub gl_size: [f32; 2],
+    /// Size in pixels of the viewport the host draws the terminal into, it
+    /// is set again after the offscreen passes.
     pub frame_size: [u32; 2],
+    /// Grid size with [`GridSizing::Fixed`], otherwise computed from
+    /// `frame_size` and this value is ignored.
     pub chars_size: [usize; 2],
+    pub sizing: GridSizing,
 
     pub back_color: [u8; 3],
     pub color: [u8; 3],
+
+    pub glyph_rendering: GlyphRendering,
+    /// Draws the text at one texel per font pixel first and scales it up by
+    /// the largest whole factor that fits, so every font pixel covers the
+    /// same number of frame pixels. The grid is centred in the frame, pair it
+    /// with [`GridSizing::Scale`] to leave little border around it.
+    pub native_resolution: bool,
+    /// Simulates a raster of this many lines: the text is drawn that many
+    /// pixels tall and every line is lit by a beam with a dark gap to the
+    /// next, wider where it is brighter. Sizing and native resolution count
+    /// these lines instead of frame pixels vertically, so a 240 line raster
+    /// with [`GridSizing::Scale`] of 1 has rows 12 lines tall. Counts under 1
+    /// are treated as 1.
+    pub raster_lines: Option<u32>,
+    /// Phosphor pattern of a colour tube drawn over the picture.
+    pub mask: Option<PhosphorMask>,
+    /// Rolls a bright band down the screen. This animates continuously, so it
+    /// forces a redraw every frame: [`CRTTerm::needs_redraw`] is always true
+    /// and a host redrawing on demand never idles.
+    pub rolling_scanline: bool,
+    /// Draws even and odd lines on alternate frames, the phosphor fade
+    /// carrying the other field, so text flickers and moving text combs like
+    /// on an interlaced tube. Like the rolling scanline this animates
+    /// continuously.
+    pub interlaced: bool,
+    /// Passes the text through a simulated composite video signal before the
+    /// effects, for colour artifacts at sharp edges, dot crawl and colour
+    /// bleeding from the narrow chroma bandwidth. The dot crawl animates
+    /// continuously.
+    pub composite: Option<CompositeSignal>,
+    /// Cursor used until an application picks its own with DECSCUSR.
+    pub cursor_style: CursorStyle,
+    /// What applications may do with the clipboard through OSC 52.
+    pub clipboard_policy: ClipboardPolicy,
+}
+
+/// How the character grid follows the size of the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GridSizing {
+    /// Keeps `chars_size`, glyphs stretch with the frame.
+    #[default]
+    Fixed,
+    /// Fits as many cells as possible of font pixels this many frame pixels
+    /// wide and tall. The grid is still stretched over the frame, so pixels
+    /// only come out as exact squares with [`ScreenInfo::native_resolution`].
+    Scale(u32),
+    /// Fits as many cells as possible of about this size in frame pixels,
+    /// spacing included.
+    CellSize([f32; 2]),
+}
+
+impl ScreenInfo {
+    /// Size in pixels of the frame the text is drawn into, before effects.
+    fn raster_size(&self) -> [u32; 2] {
+        [self.frame_size[0], self.raster_lines.map_or(self.frame_size[1], |lines| lines.max(1))]
+    }
+
+    /// `chars_size` as given by the sizing policy, at least one cell and at
+    /// most [`MAX_CHARS_SIZE`].
+    fn resolved_chars_size(&self) -> [usize; 2] {
+        let frame = self.raster_size().map(|s| s as f32 * CRT_SCALE);
+        let cell = [FONT_CHAR_WIDTH + FONT_SPACING_X, FONT_CHAR_HEIGHT + FONT_SPACING_Y];
+
+        let size = match self.sizing {
+            GridSizing::Fixed => return self.chars_size.map(|s| s.clamp(1, MAX_CHARS_SIZE)),
+            GridSizing::Scale(scale) => {
+                let scale = scale.max(1) as f32;
+                // the last column has no spacing after it
+                [
+                    (frame[0] / scale + FONT_SPACING_X as f32) / cell[0] as f32,
+                    frame[1] / scale / cell[1] as f32,
+                ]
+            }
+            GridSizing::CellSize(size) => {
+                // NaN and sizes below a pixel become a pixel
+                let size = size.map(|s| s.max(1.0));
+                [frame[0] / size[0], frame[1] / size[1]]
+            }
+        };
+        size.map(|s| (s as usize).clamp(1, MAX_CHARS_SIZE))
+    }
+}
+
+/// Colour encoding of the simulated composite signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompositeSignal {
+    /// Chroma on a 3.58 MHz subcarrier, the pattern of artifacts crawling
+    /// every frame.
+    Ntsc,
+    /// Chroma on a 4.43 MHz subcarrier with the phase of V alternating every
+    /// line, which the decoder averages out with the previous line.
+    Pal,
+}
+
+/// Arrangement of the red, green and blue phosphors on the tube.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaskKind {
+    /// Continuous vertical stripes, as on Trinitron tubes.
+    #[default]
+    ApertureGrille,
+    /// Stripes broken into slots, staggered between neighbouring triads.
+    SlotMask,
+    /// Dots in rows, every other row shifted by half a triad.
+    ShadowMask,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhosphorMask {
+    pub kind: MaskKind,
+    /// Width of one red, green and blue triad in frame pixels. At 3 every
+    /// phosphor is one pixel wide, smaller values blur into flat colour.
+    /// Pitches under 1 are treated as 1.
+    pub pitch: f32,
+}
+
+/// Clipboard access granted to applications. Reading is off by default, as
+/// it lets any program on the terminal, including ones on remote hosts, see
+/// whatever was copied on the desktop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipboardPolicy {
+    Deny,
+    #[default]
+    WriteOnly,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+impl Default for CursorStyle {
+    fn default() -> Self {
+        Self {
+            shape: CursorShape::Block,
+            blinking: true,
+        }
+    }
+}
+
+/// Requests from the child process for the host, see [`CRTTerm::take_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TermEvent {
+    /// The window title was set by OSC 0 or 2, or popped by XTWINOPS 23.
+    Title(String),
+    /// The icon name was set by OSC 0 or 1, or popped by XTWINOPS 23.
+    IconName(String),
+    /// OSC 52 asked to copy text to the clipboard.
+    SetClipboard(String),
+    /// OSC 52 asked for the clipboard contents. The host answers with
+    /// [`CRTTerm::clipboard_input`].
+    QueryClipboard,
+    /// A link was opened with [`CRTTerm::open_link`].
+    OpenLink(Link),
+}
+
+/// How the glyph pass turns the character grid into pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlyphRendering {
+    /// One instanced quad per cell, generated on the GPU from the cell buffer.
+    #[default]
+    Instanced,
+    /// A single full-screen pass that looks every pixel up in a texture holding
+    /// one texel per cell. Cost no longer depends on the amount of text.
+    CellTexture,
 }
 
 pub struct CRTTerm<C: HasContext> {
     gl: Arc<C>,
+    res: Arc<CrtResources<C>>,
 
     screen: ScreenInfo,
 
     main_quad_buf: C::Buffer,
-    full_quad_buf: C::Buffer,
-    font_buf: C::Buffer,
-    cursor_buf: C::Buffer,
+    cell_buf: C::Buffer,
     main_buf_verts: C::VertexArray,
-    full_buf_verts: C::VertexArray,
-    font_buf_verts: C::VertexArray,
-    cursor_buf_verts: C::VertexArray,
-
-    default_program: C::Program,
-    white_program: C::Program,
-    crt_fading_program: C::Program,
-    crt_warp_program: C::Program,
-    crt_effects_program: C::Program,
-
-    font_texture: C::Texture,
+    glyph_verts: C::VertexArray,
+
+    cell_texture: C::Texture,
+    /// Text at native resolution, see [`ScreenInfo::native_resolution`].
+    text_texture: C::Texture,
+    text_framebuffer: C::Framebuffer,
+    /// Frame after the composite signal, see [`ScreenInfo::composite`].
+    composite_texture: C::Texture,
+    composite_framebuffer: C::Framebuffer,
     fade_texture: C::Texture,
     fade_framebuffer: C::Framebuffer,
 
     effects_texture: C::Texture,
     effects_framebuffer: C::Framebuffer,
 
-    start_time: Instant,
-    font_buffer_cache: Vec<u8>,
-    font_buffer_vertices: u32,
+    effects_params_buf: C::Buffer,
 
-    pub cursor: [usize; 2],
-    cursor_blinker: i32,
-    pub chars: Box<[Box<[char]>]>,
+    start_time: Instant,
+    cell_cache: Vec<CellData>,
+    dirty_rows: Vec<bool>,
+
+    redraw_requested: bool,
+    settle_frames: u32,
+    drawn_cursor: Option<([usize; 2], CursorShape)>,
+    /// Frames drawn, picking the interlaced field and the phase of the
+    /// composite subcarrier.
+    frame_count: u32,
+
+    cursor: [usize; 2],
+    cursor_blink_start: Instant,
+    /// Set by DECSCUSR, overriding [`ScreenInfo::cursor_style`].
+    cursor_style: Option<CursorStyle>,
+    chars: Box<[Box<[char]>]>,
+    /// OSC 8 hyperlink of every cell in `chars`, see [`CRTTerm::hyperlinks`].
+    links: Box<[Box<[u16]>]>,
+    /// Rows of `chars` that continue on the next row because printing went
+    /// past the last column, so that resizing can join them again.
+    wrapped: Vec<bool>,
+
+    /// Whether full-screen programs switched `chars` to the alternate screen.
+    alt_screen: bool,
+    /// Contents of the screen that is not shown, primary or alternate.
+    other_chars: Box<[Box<[char]>]>,
+    other_links: Box<[Box<[u16]>]>,
+    other_wrapped: Vec<bool>,
+    /// Cursor saved by DECSC, one for the primary and one for the alternate screen.
+    saved_cursors: [[usize; 2]; 2],
+    /// Rows between the DECSTBM margins, which scroll when a line feed
+    /// reaches the bottom margin.
+    scroll_region: Range<usize>,
+    /// Columns with a tab stop, set every [`TAB_WIDTH`] columns by default.
+    tab_stops: Vec<bool>,
+    /// Set after printing into the last column with autowrap on. The cursor
+    /// stays there and the line only wraps when the next character arrives.
+    pending_wrap: bool,
+
+    /// Rows that scrolled off the top of the primary screen, oldest first.
+    scrollback: VecDeque<Box<[char]>>,
+    scrollback_links: VecDeque<Box<[u16]>>,
+    scrollback_wrapped: VecDeque<bool>,
+    /// How many lines the view is scrolled back into `scrollback`.
+    view_offset: usize,
+    selection: Option<Selection>,
+
+    /// Targets of OSC 8 hyperlinks, cells store their index plus one. Entries
+    /// no cell refers to any more are freed when the table fills up.
+    hyperlinks: Vec<Option<Hyperlink>>,
+    /// Index in `hyperlinks` of every entry, to find links printed again.
+    hyperlink_indices: HashMap<Hyperlink, usize>,
+    /// Hyperlink given to printed characters, 0 for none.
+    active_link: u16,
+    hover: Option<Hover>,
+    search: Option<Search>,
+
+    title: String,
+    icon_name: String,
+    /// Titles and icon names saved by XTWINOPS 22.
+    title_stack: Vec<(String, String)>,
+
+    parser: Parser,
+    modes: Modes,
+    /// Replies to queries from the child process, waiting to be sent back.
+    responses: Vec<u8>,
+    events: Vec<TermEvent>,
 }
 
-const VERT_SHADER: &str = r#"#version 330 core
-  layout (location = 0) in vec2 pos;
-  layout (location = 1) in vec2 uv_in;
+pub(crate) const FONT_5X11: &[u8] = include_bytes!("../font_5x11.png");
+pub(crate) const FONT_COLS: usize = 32;
+const FONT_ROWS: usize = 4;
+pub(crate) const FONT_CHAR_WIDTH: usize = 5;
+pub(crate) const FONT_CHAR_HEIGHT: usize = 11;
+pub(crate) const FONT_IMAGE_SPACING_X: usize = 1;
+pub(crate) const FONT_IMAGE_SPACING_Y: usize = 1;
+pub(crate) const FONT_SPACING_X: usize = 1;
+pub(crate) const FONT_SPACING_Y: usize = 1;
 
-  out vec2 uv;
-  void main() {
-    gl_Position = vec4(pos.x, pos.y, 0.0, 1.0);
-    uv = uv_in;
-  }
-"#;
+const CRT_SCALE: f32 = 0.99;
 
-const FRAG_SHADER: &str = r#"#version 330 core
-  uniform sampler2D sampler;
-  in vec2 uv;
+const SCROLLBACK_LINES: usize = 1000;
 
-  void main() {
-    gl_FragColor = texture(sampler, uv);
-  }
-"#;
+/// Most columns and rows of the grid, whatever the sizing policy asks for.
+const MAX_CHARS_SIZE: usize = 2048;
 
-const WHITE_FRAG_SHADER: &str = r#"#version 330 core
-  uniform sampler2D sampler;
-  in vec2 uv;
+const TAB_WIDTH: usize = 8;
 
-  void main() {
-    gl_FragColor = vec4(1.0);
-  }
-"#;
+/// Depth of the XTWINOPS title stack, as in xterm.
+const TITLE_STACK_LEN: usize = 10;
 
-const CRT_WARP_FRAG_SHADER: &str = include_str!("crt_warp.frag.glsl");
-const CRT_FADING_FRAG_SHADER: &str = include_str!("crt_fading.frag.glsl");
-const CRT_EFFECTS_SHADER: &str = include_str!("crt_effects.frag.glsl");
+/// Most bytes of replies kept for a host that does not take them.
+const MAX_PENDING_RESPONSES: usize = 4096;
+/// Most events kept for a host that does not take them.
+const MAX_PENDING_EVENTS: usize = 64;
 
-const FONT_5X11: &[u8] = include_bytes!("../font_5x11.png");
-const FONT_COLS: usize = 32;
-const FONT_ROWS: usize = 4;
-const FONT_CHAR_WIDTH: usize = 5;
-const FONT_CHAR_HEIGHT: usize = 11;
-const FONT_IMAGE_SPACING_X: usize = 1;
-const FONT_IMAGE_SPACING_Y: usize = 1;
-const FONT_SPACING_X: usize = 1;
-const FONT_SPACING_Y: usize = 1;
+/// Cell flag bits, shared with the glyph shaders.
+pub(crate) const CELL_SELECTED: u16 = 1 << 0;
+pub(crate) const CELL_CURSOR_BLOCK: u16 = 1 << 1;
+pub(crate) const CELL_CURSOR_UNDERLINE: u16 = 1 << 2;
+pub(crate) const CELL_CURSOR_BAR: u16 = 1 << 3;
+pub(crate) const CELL_UNDERLINE: u16 = 1 << 4;
+pub(crate) const CELL_MATCH: u16 = 1 << 5;
+pub(crate) const CELL_CURRENT_MATCH: u16 = 1 << 6;
 
-const CRT_SCALE: f32 = 0.99;
+const CURSOR_BLINK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `crt_fading.frag.glsl` takes a fully lit pixel down to black in 7 frames,
+/// so that many frames have to be rendered after the last visible change.
+const PHOSPHOR_SETTLE_FRAMES: u32 = 8;
 
 const DEBUG_NO_WARP: bool = false;
 
 impl<C: HasContext> CRTTerm<C> {
+    /// Creates a terminal with its own [`CrtResources`]. Use
+    /// [`CRTTerm::with_resources`] when several terminals share a GL context.
     pub fn new(gl: Arc<C>, screen: ScreenInfo) -> Self {
-        let font_image =
-            image::load_from_memory_with_format(FONT_5X11, image::ImageFormat::Png).unwrap();
-        let font_image = font_image.into_rgba8();
+        Self::with_resources(Arc::new(CrtResources::new(gl)), screen)
+    }
+
+    pub fn with_resources(res: Arc<CrtResources<C>>, screen: ScreenInfo) -> Self {
+        let gl = res.gl.clone();
+        let screen = ScreenInfo {
+            chars_size: screen.resolved_chars_size(),
+            ..screen
+        };
 
         let main_quad_buf = unsafe { gl.create_buffer().unwrap() };
-        let full_quad_buf = unsafe { gl.create_buffer().unwrap() };
-        let font_buf = unsafe { gl.create_buffer().unwrap() };
-        let cursor_buf = unsafe { gl.create_buffer().unwrap() };
+        let cell_buf = unsafe { gl.create_buffer().unwrap() };
 
         let main_buf_verts = unsafe { gl.create_vertex_array().unwrap() };
-        let full_buf_verts = unsafe { gl.create_vertex_array().unwrap() };
-        let font_buf_verts = unsafe { gl.create_vertex_array().unwrap() };
-        let cursor_buf_verts = unsafe { gl.create_vertex_array().unwrap() };
+        let glyph_verts = unsafe { gl.create_vertex_array().unwrap() };
+
+        let cell_texture = unsafe { gl.create_texture().unwrap() };
 
-        let default_program = unsafe { gl.create_program().unwrap() };
-        let white_program = unsafe { gl.create_program().unwrap() };
-        let crt_warp_program = unsafe { gl.create_program().unwrap() };
-        let crt_fading_program = unsafe { gl.create_program().unwrap() };
-        let crt_effects_program = unsafe { gl.create_program().unwrap() };
+        let text_texture = unsafe { gl.create_texture().unwrap() };
+        let text_framebuffer = unsafe { gl.create_framebuffer().unwrap() };
 
-        let font_texture = unsafe { gl.create_texture().unwrap() };
+        let composite_texture = unsafe { gl.create_texture().unwrap() };
+        let composite_framebuffer = unsafe { gl.create_framebuffer().unwrap() };
 
         let fade_texture = unsafe { gl.create_texture().unwrap() };
         let fade_framebuffer = unsafe { gl.create_framebuffer().unwrap() };
@@ -126,6 +391,8 @@ impl<C: HasContext> CRTTerm<C> {
         let effects_texture = unsafe { gl.create_texture().unwrap() };
         let effects_framebuffer = unsafe { gl.create_framebuffer().unwrap() };
 
+        let effects_params_buf = unsafe { gl.create_buffer().unwrap() };
+
         unsafe {
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(main_quad_buf));
             gl.buffer_data_u8_slice(
@@ -148,81 +415,46 @@ impl<C: HasContext> CRTTerm<C> {
             gl.bind_vertex_array(None);
             gl.bind_buffer(glow::ARRAY_BUFFER, None);
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(font_buf));
-            gl.bind_vertex_array(Some(font_buf_verts));
+            gl.bind_vertex_array(Some(glyph_verts));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(res.glyph_quad_buf));
             gl.vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 16, 0);
-            gl.vertex_attrib_pointer_f32(1, 2, glow::FLOAT, false, 16, 8);
             gl.enable_vertex_attrib_array(0);
-            gl.enable_vertex_attrib_array(1);
-            gl.bind_vertex_array(None);
-            gl.bind_buffer(glow::ARRAY_BUFFER, None);
 
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(cursor_buf));
-            gl.bind_vertex_array(Some(cursor_buf_verts));
-            gl.vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 16, 0);
-            gl.vertex_attrib_pointer_f32(1, 2, glow::FLOAT, false, 16, 8);
-            gl.enable_vertex_attrib_array(0);
-            gl.enable_vertex_attrib_array(1);
-            gl.bind_vertex_array(None);
-            gl.bind_buffer(glow::ARRAY_BUFFER, None);
-
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(full_quad_buf));
-            gl.buffer_data_u8_slice(
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(cell_buf));
+            gl.buffer_data_size(
                 glow::ARRAY_BUFFER,
-                &create_quad_data_tri_strip(
-                    [-1.0, -1.0],
-                    [2.0, 2.0],
-                    [0.0, 0.0],
-                    [1.0, 1.0],
-                    false,
-                ),
-                glow::STATIC_DRAW,
+                cell_buffer_size(screen.chars_size),
+                glow::DYNAMIC_DRAW,
             );
-
-            gl.bind_vertex_array(Some(full_buf_verts));
-            gl.vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 16, 0);
-            gl.vertex_attrib_pointer_f32(1, 2, glow::FLOAT, false, 16, 8);
-            gl.enable_vertex_attrib_array(0);
+            gl.vertex_attrib_pointer_i32(1, 4, glow::UNSIGNED_SHORT, 8, 0);
+            gl.vertex_attrib_divisor(1, 1);
             gl.enable_vertex_attrib_array(1);
             gl.bind_vertex_array(None);
             gl.bind_buffer(glow::ARRAY_BUFFER, None);
 
-            construct_program(
-                gl.as_ref(),
-                crt_warp_program,
-                VERT_SHADER,
-                CRT_WARP_FRAG_SHADER,
-            );
-            construct_program(
-                gl.as_ref(),
-                crt_fading_program,
-                VERT_SHADER,
-                CRT_FADING_FRAG_SHADER,
+            gl.bind_buffer(glow::UNIFORM_BUFFER, Some(effects_params_buf));
+            gl.buffer_data_size(
+                glow::UNIFORM_BUFFER,
+                std::mem::size_of::<EffectParams>() as i32,
+                glow::DYNAMIC_DRAW,
             );
-            construct_program(
-                gl.as_ref(),
-                crt_effects_program,
-                VERT_SHADER,
-                CRT_EFFECTS_SHADER,
-            );
-            construct_program(gl.as_ref(), default_program, VERT_SHADER, FRAG_SHADER);
-            construct_program(gl.as_ref(), white_program, VERT_SHADER, WHITE_FRAG_SHADER);
+            gl.bind_buffer(glow::UNIFORM_BUFFER, None);
 
-            gl.bind_texture(glow::TEXTURE_2D, Some(font_texture));
-            gl.enable(glow::BLEND);
-            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
-            gl.tex_image_2d(
+            gl.bind_texture(glow::TEXTURE_2D, Some(cell_texture));
+            alloc_cell_texture(gl.as_ref(), screen.chars_size);
+            gl.tex_parameter_i32(
                 glow::TEXTURE_2D,
-                0,
-                glow::RGBA as i32,
-                font_image.width() as i32,
-                font_image.height() as i32,
-                0,
-                glow::RGBA,
-                glow::UNSIGNED_BYTE,
-                Some(std::mem::transmute(font_image.as_ref())),
+                glow::TEXTURE_MAG_FILTER,
+                glow::NEAREST as i32,
             );
-            gl.generate_mipmap(glow::TEXTURE_2D);
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::NEAREST as i32,
+            );
+
+            gl.bind_texture(glow::TEXTURE_2D, Some(text_texture));
+            alloc_text_texture(gl.as_ref(), screen.chars_size);
             gl.tex_parameter_i32(
                 glow::TEXTURE_2D,
                 glow::TEXTURE_MAG_FILTER,
@@ -233,14 +465,23 @@ impl<C: HasContext> CRTTerm<C> {
                 glow::TEXTURE_MIN_FILTER,
                 glow::NEAREST as i32,
             );
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(text_framebuffer));
+            gl.framebuffer_texture(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                Some(text_texture),
+                0,
+            );
+            gl.draw_buffers(&[glow::COLOR_ATTACHMENT0]);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
 
             gl.bind_texture(glow::TEXTURE_2D, Some(fade_texture));
             gl.tex_image_2d(
                 glow::TEXTURE_2D,
                 0,
                 glow::RGB as i32,
-                screen.frame_size[0] as i32,
-                screen.frame_size[1] as i32,
+                screen.raster_size()[0] as i32,
+                screen.raster_size()[1] as i32,
                 0,
                 glow::RGB,
                 glow::UNSIGNED_BYTE,
@@ -283,6 +524,53 @@ impl<C: HasContext> CRTTerm<C> {
             gl.draw_buffers(&[glow::COLOR_ATTACHMENT0]);
             gl.bind_framebuffer(glow::FRAMEBUFFER, None);
 
+            gl.bind_texture(glow::TEXTURE_2D, Some(composite_texture));
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGB as i32,
+                screen.raster_size()[0] as i32,
+                screen.raster_size()[1] as i32,
+                0,
+                glow::RGB,
+                glow::UNSIGNED_BYTE,
+                None,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::LINEAR as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::LINEAR as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_R,
+                glow::CLAMP_TO_BORDER as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_S,
+                glow::CLAMP_TO_BORDER as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_T,
+                glow::CLAMP_TO_BORDER as i32,
+            );
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(composite_framebuffer));
+            gl.framebuffer_texture(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                Some(composite_texture),
+                0,
+            );
+            gl.draw_buffers(&[glow::COLOR_ATTACHMENT0]);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+
             gl.bind_texture(glow::TEXTURE_2D, Some(effects_texture));
             gl.tex_image_2d(
                 glow::TEXTURE_2D,
@@ -324,154 +612,358 @@ impl<C: HasContext> CRTTerm<C> {
             // gl.bind_buffer(glow::ARRAY_BUFFER, None);
         }
 
-        //let time_uniform = unsafe { gl.get_uniform_location(crt_warp_program, "time") };
-
         Self {
             gl,
+            res,
             screen,
-            full_quad_buf,
             main_quad_buf,
-            font_buf,
-            cursor_buf,
+            cell_buf,
 
             main_buf_verts,
-            full_buf_verts,
-            font_buf_verts,
-            cursor_buf_verts,
+            glyph_verts,
 
-            default_program,
-            white_program,
-            crt_warp_program,
-            crt_fading_program,
-            crt_effects_program,
-
-            font_texture,
+            cell_texture,
+            text_texture,
+            text_framebuffer,
+            composite_texture,
+            composite_framebuffer,
             fade_framebuffer,
             fade_texture,
 
             effects_texture,
             effects_framebuffer,
 
-            //time_uniform,
+            effects_params_buf,
+
             start_time: Instant::now(),
-            font_buffer_cache: vec![],
-            font_buffer_vertices: 0,
+            cell_cache: vec![[0; 4]; screen.chars_size[0] * screen.chars_size[1]],
+            dirty_rows: vec![true; screen.chars_size[1]],
+
+            redraw_requested: true,
+            settle_frames: 0,
+            drawn_cursor: None,
+            frame_count: 0,
 
             cursor: [0, 0],
-            cursor_blinker: 0,
-            chars: (0..screen.chars_size[1])
-                .map(|_| {
-                    (0..screen.chars_size[0])
-                        .map(|_| '\0')
-                        .collect::<Vec<_>>()
-                        .into_boxed_slice()
-                })
-                .collect::<Vec<_>>()
-                .into_boxed_slice(),
+            cursor_blink_start: Instant::now(),
+            cursor_style: None,
+            chars: resize_grid(&[], screen.chars_size),
+            links: resize_grid(&[], screen.chars_size),
+            wrapped: vec![false; screen.chars_size[1]],
+
+            alt_screen: false,
+            other_chars: resize_grid(&[], screen.chars_size),
+            other_links: resize_grid(&[], screen.chars_size),
+            other_wrapped: vec![false; screen.chars_size[1]],
+            saved_cursors: [[0, 0]; 2],
+            scroll_region: 0..screen.chars_size[1],
+            tab_stops: default_tab_stops(screen.chars_size[0]),
+            pending_wrap: false,
+
+            scrollback: VecDeque::new(),
+            scrollback_links: VecDeque::new(),
+            scrollback_wrapped: VecDeque::new(),
+            view_offset: 0,
+            selection: None,
+
+            hyperlinks: Vec::new(),
+            hyperlink_indices: HashMap::new(),
+            active_link: 0,
+            hover: None,
+            search: None,
+
+            title: String::new(),
+            icon_name: String::new(),
+            title_stack: Vec::new(),
+
+            parser: Parser::default(),
+            modes: Modes::default(),
+            responses: Vec::new(),
+            events: Vec::new(),
         }
     }
 
-    pub fn update(&mut self) {
-        self.font_buffer_cache.clear();
-        self.font_buffer_vertices = 0;
+    /// Feeds output of the child process to the terminal, interpreting
+    /// control sequences.
+    pub fn process(&mut self, bytes: &[u8]) {
+        let mut parser = std::mem::take(&mut self.parser);
+        for &byte in bytes {
+            parser.advance(byte, &mut |action| self.perform(action));
+        }
+        self.parser = parser;
+        self.cursor_blink_start = Instant::now();
+        self.refresh_hover();
+        self.refresh_search();
+    }
 
-        unsafe {
-            let gl = &self.gl;
-            gl.bind_vertex_array(Some(self.full_buf_verts));
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.full_quad_buf));
-            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fade_framebuffer));
-            gl.framebuffer_texture(
-                glow::FRAMEBUFFER,
-                glow::COLOR_ATTACHMENT0,
-                Some(self.fade_texture),
-                0,
-            );
+    /// Replies to device attribute, status and window queries seen by
+    /// [`CRTTerm::process`]. The host must write them to the child process.
+    /// Replies that do not fit in the pending buffer are dropped.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
 
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.fade_texture));
-            gl.use_program(Some(self.crt_fading_program));
+    /// Events raised by [`CRTTerm::process`] since the last call, oldest first.
+    /// Only the latest events are kept when the host does not take them.
+    pub fn take_events(&mut self) -> Vec<TermEvent> {
+        std::mem::take(&mut self.events)
+    }
 
-            gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+    /// Window title last set by the child process.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
 
-            let gl_pos = [-1.0; 2];
-            let gl_size = [2.0; 2];
+    /// Rows of the screen, top first. This replaces the former public
+    /// `chars` field: writes have to go through [`CRTTerm::process`] so the
+    /// changed rows are uploaded again.
+    pub fn chars(&self) -> &[Box<[char]>] {
+        &self.chars
+    }
 
-            let char_bounds_w = gl_size[0] / self.screen.chars_size[0] as f32;
+    /// Column and row of the cursor on the screen. This replaces the former
+    /// public `cursor` field, as control functions rely on it staying inside
+    /// the grid.
+    pub fn cursor(&self) -> [usize; 2] {
+        self.cursor
+    }
 
-            // remap char bounds to not include FONT_SPACING_X at the last column
-            let char_bounds_w = char_bounds_w * (self.screen.chars_size[0] * (FONT_CHAR_WIDTH + FONT_SPACING_X)) as f32
-                / (self.screen.chars_size[0] * FONT_CHAR_WIDTH + (self.screen.chars_size[0] - 1) * FONT_SPACING_X) as f32;
+    /// Columns and rows of the grid, as resolved from the sizing policy. The
+    /// host passes this on to the pty after [`CRTTerm::screen_changed`].
+    pub fn chars_size(&self) -> [usize; 2] {
+        self.screen.chars_size
+    }
 
-            let char_bounds_h = gl_size[1] / self.screen.chars_size[1] as f32;
+    /// How many lines the view is currently scrolled back into the history.
+    pub fn view_offset(&self) -> usize {
+        self.view_offset
+    }
 
-            let char_bounds_w = char_bounds_w * CRT_SCALE;
-            let char_bounds_h = char_bounds_h * CRT_SCALE;
+    /// Scrolls the view by `lines`, positive values going back into the
+    /// history. The offset is clamped to the available scrollback.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let history = if self.alt_screen { 0 } else { self.scrollback.len() };
+        let offset = self.view_offset.saturating_add_signed(lines).min(history);
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.dirty_rows.fill(true);
+            self.refresh_hover();
+        }
+    }
 
-            let char_w = (char_bounds_w / (FONT_CHAR_WIDTH + FONT_SPACING_X) as f32)
-                * FONT_CHAR_WIDTH as f32;
-            let char_h = (char_bounds_h / (FONT_CHAR_HEIGHT + FONT_SPACING_Y) as f32)
-                * FONT_CHAR_HEIGHT as f32;
+    /// Starts a new selection at `cell`, a position on screen as returned by
+    /// [`CRTTerm::cell_at`].
+    pub fn start_selection(&mut self, cell: [usize; 2], kind: SelectionKind) {
+        self.mark_selection_dirty();
+        self.selection = Some(Selection::new(kind, self.view_point(cell)));
+        self.mark_selection_dirty();
+    }
 
-            let gl_offset_x = gl_size[0] * (1.0 - CRT_SCALE) * 0.5;
-            let gl_offset_y = gl_size[1] * (1.0 - CRT_SCALE) * 0.5;
+    /// Moves the free end of the current selection to `cell`.
+    pub fn extend_selection(&mut self, cell: [usize; 2]) {
+        let head = self.view_point(cell);
+        if self.selection.is_some_and(|s| s.head != head) {
+            self.mark_selection_dirty();
+            if let Some(selection) = &mut self.selection {
+                selection.head = head;
+            }
+            self.mark_selection_dirty();
+        }
+    }
 
-            for (y, row) in self.chars.iter().enumerate() {
-                for (x, char) in row.iter().copied().enumerate() {
-                    if char == '\0' {
-                        continue;
-                    }
+    pub fn clear_selection(&mut self) {
+        self.mark_selection_dirty();
+        self.selection = None;
+    }
 
-                    let gl_x = gl_offset_x + gl_pos[0] + x as f32 * char_bounds_w;
-                    let gl_y =  gl_pos[1] + gl_size[1]
-                        - char_h
-                        - y as f32 * char_bounds_h
-                        - gl_offset_y;
-
-                    add_glyph(
-                        &mut self.font_buffer_cache,
-                        char,
-                        [gl_x, gl_y],
-                        [char_w, char_h],
-                    );
+    /// Text covered by the current selection, with rows separated by `\n`.
+    pub fn selected_text(&self) -> Option<String> {
+        self.selection_range()
+            .map(|range| range.text(|line| self.line(line)))
+    }
+
+    /// Finds the cell displayed under `pos`, given in framebuffer pixels with
+    /// the origin in the top-left corner (the space of [`ScreenInfo::frame_size`]).
+    ///
+    /// Returns `None` when `pos` is outside the curved glass or in the border
+    /// around the character grid.
+    pub fn cell_at(&self, pos: [f32; 2]) -> Option<[usize; 2]> {
+        let frame_size = self.screen.frame_size.map(|s| s.max(1) as f32);
+        let gl_pos = [
+            pos[0] / frame_size[0] * 2.0 - 1.0,
+            1.0 - pos[1] / frame_size[1] * 2.0,
+        ];
+
+        let uv = if DEBUG_NO_WARP {
+            gl_pos.map(|p| (p + 1.0) * 0.5)
+        } else {
+            let uv = [0, 1].map(|i| (gl_pos[i] - self.screen.gl_pos[i]) / self.screen.gl_size[i]);
+            // the warp pass samples the flat image at `warp(uv)`, so the same
+            // mapping takes a point on the glass back to the grid
+            warp(uv)
+        };
+        if uv.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return None;
+        }
+
+        GridLayout::new(&self.screen).cell_at(uv.map(|c| c * 2.0 - 1.0))
+    }
+
+    /// Whether calling [`CRTTerm::update`] now would produce a different image
+    /// than the last one, either because of new content, cursor movement or
+    /// blinking, phosphor that is still fading out, or a time-based effect.
+    pub fn needs_redraw(&self) -> bool {
+        self.redraw_requested
+            || self.screen.rolling_scanline
+            || self.screen.interlaced
+            || self.screen.composite.is_some()
+            || self.settle_frames > 0
+            || self.dirty_rows.contains(&true)
+            || self.drawn_cursor != self.visible_cursor(Instant::now())
+    }
+
+    /// When the next redraw will be needed if nothing else changes in the
+    /// meantime. Returns the current time if a redraw is already due.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        let now = Instant::now();
+        if self.needs_redraw() {
+            return Some(now);
+        }
+
+        // only a blinking cursor changes the image on its own
+        let style = self.cursor_style.unwrap_or(self.screen.cursor_style);
+        if !style.blinking || !self.modes.cursor_visible {
+            return None;
+        }
+
+        let interval = CURSOR_BLINK_INTERVAL.as_nanos();
+        let phases = now.saturating_duration_since(self.cursor_blink_start).as_nanos() / interval + 1;
+        Some(self.cursor_blink_start + Duration::from_nanos((phases * interval) as u64))
+    }
+
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let visible_cursor = self.visible_cursor(now);
+
+        if self.redraw_requested
+            || self.dirty_rows.contains(&true)
+            || self.drawn_cursor != visible_cursor
+        {
+            self.settle_frames = PHOSPHOR_SETTLE_FRAMES;
+        } else {
+            self.settle_frames = self.settle_frames.saturating_sub(1);
+        }
+        self.redraw_requested = false;
+
+        // the cursor is drawn as flags of its cell
+        if self.drawn_cursor != visible_cursor {
+            for ([_, y], _) in [self.drawn_cursor, visible_cursor].into_iter().flatten() {
+                if let Some(dirty) = self.dirty_rows.get_mut(y) {
+                    *dirty = true;
                 }
             }
+        }
+        self.drawn_cursor = visible_cursor;
 
-            if self.cursor_blinker > 0 {
-                let gl_x = gl_offset_x + gl_pos[0] + self.cursor[0] as f32 * char_bounds_w;
-                let gl_y = gl_pos[1] + gl_size[1]
-                    - (self.cursor[1] + 1) as f32 * char_bounds_h - gl_offset_y;
+        self.upload_dirty_rows();
 
-                gl.bind_vertex_array(Some(self.cursor_buf_verts));
-                gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.cursor_buf));
-                gl.buffer_data_u8_slice(
-                    glow::ARRAY_BUFFER,
-                    &create_quad_data_tri_strip(
-                        [gl_x, gl_y],
-                        [char_bounds_w, char_bounds_h],
-                        [0.0, 0.0],
-                        [0.0, 0.0],
-                        false,
-                    ),
-                    glow::STREAM_DRAW,
+        let res = self.res.as_ref();
+        let uniforms = &res.uniforms;
+
+        unsafe {
+            let gl = &self.gl;
+            // offscreen passes draw over their whole target, the warp pass
+            // goes to the host's viewport, which covers `frame_size`
+            let [frame_width, frame_height] = self.screen.frame_size.map(|v| v as i32);
+            let raster_size = self.screen.raster_size();
+            gl.viewport(0, 0, raster_size[0] as i32, raster_size[1] as i32);
+
+            gl.bind_vertex_array(Some(res.full_buf_verts));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(res.full_quad_buf));
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fade_framebuffer));
+            gl.framebuffer_texture(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                Some(self.fade_texture),
+                0,
+            );
+
+            uniforms.fading_frame.bind(gl.as_ref(), self.fade_texture);
+            gl.use_program(Some(res.crt_fading_program));
+
+            gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+
+            let layout = GridLayout::new(&self.screen);
+            let field = if self.screen.interlaced {
+                (self.frame_count % 2) as i32
+            } else {
+                -1
+            };
+            if self.screen.native_resolution {
+                // every pixel of the text texture belongs to exactly one cell,
+                // so cells are written as they are without blending
+                gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.text_framebuffer));
+                gl.framebuffer_texture(
+                    glow::FRAMEBUFFER,
+                    glow::COLOR_ATTACHMENT0,
+                    Some(self.text_texture),
+                    0,
+                );
+                let [width, height] = native_text_size(self.screen.chars_size);
+                gl.viewport(0, 0, width, height);
+                gl.clear_buffer_f32_slice(glow::COLOR, 0, &[0.0; 4]);
+                gl.disable(glow::BLEND);
+
+                self.draw_glyphs(&GridLayout::native(self.screen.chars_size), -1);
+
+                gl.enable(glow::BLEND);
+                gl.viewport(0, 0, raster_size[0] as i32, raster_size[1] as i32);
+                gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fade_framebuffer));
+
+                let size = layout.size();
+                gl.bind_vertex_array(Some(res.full_buf_verts));
+                uniforms.upscale_text.bind(gl.as_ref(), self.text_texture);
+                gl.use_program(Some(res.text_upscale_program));
+                gl.uniform_2_f32(
+                    uniforms.upscale_origin.as_ref(),
+                    layout.origin[0],
+                    layout.origin[1] - size[1],
                 );
-                gl.use_program(Some(self.white_program));
+                gl.uniform_2_f32(uniforms.upscale_size.as_ref(), size[0], size[1]);
+                gl.uniform_1_i32(uniforms.upscale_field.as_ref(), field);
                 gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+            } else {
+                self.draw_glyphs(&layout, field);
             }
 
-            gl.bind_vertex_array(Some(self.font_buf_verts));
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.font_buf));
-            gl.buffer_data_u8_slice(
-                glow::ARRAY_BUFFER,
-                &self.font_buffer_cache,
-                glow::STREAM_DRAW,
-            );
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.font_texture));
-            gl.use_program(Some(self.default_program));
+            let mut frame_texture = self.fade_texture;
+            if let Some(signal) = self.screen.composite {
+                gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.composite_framebuffer));
+                gl.framebuffer_texture(
+                    glow::FRAMEBUFFER,
+                    glow::COLOR_ATTACHMENT0,
+                    Some(self.composite_texture),
+                    0,
+                );
 
-            gl.draw_arrays(glow::TRIANGLES, 0, self.font_buffer_cache.len() as i32 / 16);
+                gl.bind_vertex_array(Some(res.full_buf_verts));
+                uniforms.composite_frame.bind(gl.as_ref(), self.fade_texture);
+                gl.use_program(Some(res.composite_program));
+                gl.uniform_1_i32(uniforms.composite_standard.as_ref(), signal as i32);
+                gl.uniform_1_i32(
+                    uniforms.composite_frame_count.as_ref(),
+                    self.frame_count as i32,
+                );
+                gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+
+                frame_texture = self.composite_texture;
+            }
 
             if DEBUG_NO_WARP {
                 gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                gl.viewport(0, 0, frame_width, frame_height);
             } else {
+                gl.viewport(0, 0, frame_width, frame_height);
                 gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.effects_framebuffer));
                 gl.framebuffer_texture(
                     glow::FRAMEBUFFER,
@@ -481,65 +973,119 @@ impl<C: HasContext> CRTTerm<C> {
                 );
             }
 
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.fade_texture));
-            gl.bind_vertex_array(Some(self.full_buf_verts));
-            gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.full_quad_buf));
-
-            gl.use_program(Some(self.crt_effects_program));
-
-            gl.uniform_2_f32(
-                gl.get_uniform_location(self.crt_effects_program, "pixelSize")
-                    .as_ref(),
-                1.0 / self.screen.frame_size[0] as f32,
-                1.0 / self.screen.frame_size[1] as f32,
-            );
-            gl.uniform_1_f32(
-                gl.get_uniform_location(self.crt_effects_program, "time")
-                    .as_ref(),
-                Instant::now()
-                    .saturating_duration_since(self.start_time)
-                    .as_secs_f32(),
-            );
-            gl.uniform_3_f32(
-                gl.get_uniform_location(self.crt_effects_program, "bgColor")
-                    .as_ref(),
-                self.screen.back_color[0] as f32 / 255.0,
-                self.screen.back_color[1] as f32 / 255.0,
-                self.screen.back_color[2] as f32 / 255.0,
-            );
-
-            gl.uniform_3_f32(
-                gl.get_uniform_location(self.crt_effects_program, "fgColor")
-                    .as_ref(),
-                self.screen.color[0] as f32 / 255.0,
-                self.screen.color[1] as f32 / 255.0,
-                self.screen.color[2] as f32 / 255.0,
+            uniforms.effects_frame.bind(gl.as_ref(), frame_texture);
+            gl.bind_vertex_array(Some(res.full_buf_verts));
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(res.full_quad_buf));
+
+            gl.use_program(Some(res.crt_effects_program));
+
+            let params = EffectParams {
+                pixel_size: [
+                    1.0 / self.screen.frame_size[0] as f32,
+                    1.0 / self.screen.frame_size[1] as f32,
+                ],
+                time: now.saturating_duration_since(self.start_time).as_secs_f32(),
+                bg_color: color_to_f32(self.screen.back_color),
+                fg_color: color_to_f32(self.screen.color),
+                rolling_scanline: self.screen.rolling_scanline as u32 as f32,
+                raster_lines: self.screen.raster_lines.map_or(0, |lines| lines.max(1)) as f32,
+                mask_kind: self.screen.mask.map_or(0, |mask| mask.kind as u32 + 1) as f32,
+                mask_pitch: self.screen.mask.map_or(0.0, |mask| mask.pitch.max(1.0)),
+                composite: self.screen.composite.is_some() as u32 as f32,
+                ..Default::default()
+            };
+            gl.bind_buffer(glow::UNIFORM_BUFFER, Some(self.effects_params_buf));
+            gl.buffer_sub_data_u8_slice(glow::UNIFORM_BUFFER, 0, &params.to_std140());
+            gl.bind_buffer(glow::UNIFORM_BUFFER, None);
+            gl.bind_buffer_base(
+                glow::UNIFORM_BUFFER,
+                EFFECT_PARAMS_BINDING,
+                Some(self.effects_params_buf),
             );
 
             gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
 
             if !DEBUG_NO_WARP {
                 gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                gl.viewport(0, 0, frame_width, frame_height);
 
                 gl.bind_vertex_array(Some(self.main_buf_verts));
                 gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.main_quad_buf));
-                gl.use_program(Some(self.crt_warp_program));
+                gl.use_program(Some(res.crt_warp_program));
 
-                gl.bind_texture(glow::TEXTURE_2D, Some(self.effects_texture));
+                uniforms.warp_tex.bind(gl.as_ref(), self.effects_texture);
                 gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
             }
         }
 
-        self.cursor_blinker = match self.cursor_blinker {
-            0 => -60,
-            -1 => 60,
-            b if b < 0 => b + 1,
-            b => b - 1,
-        };
+        self.frame_count = self.frame_count.wrapping_add(1);
+    }
+
+    /// Draws the cells with `layout` into the bound framebuffer, only on the
+    /// lines of `field` unless it is -1.
+    fn draw_glyphs(&self, layout: &GridLayout, field: i32) {
+        let res = self.res.as_ref();
+        let uniforms = &res.uniforms;
+
+        unsafe {
+            let gl = &self.gl;
+            match self.screen.glyph_rendering {
+                GlyphRendering::Instanced => {
+                    gl.bind_vertex_array(Some(self.glyph_verts));
+                    uniforms.glyph_font.bind(gl.as_ref(), res.font_texture);
+                    gl.use_program(Some(res.glyph_program));
+                    gl.uniform_2_f32(
+                        uniforms.glyph_origin.as_ref(),
+                        layout.origin[0],
+                        layout.origin[1],
+                    );
+                    gl.uniform_2_f32(
+                        uniforms.glyph_cell_size.as_ref(),
+                        layout.cell_size[0],
+                        layout.cell_size[1],
+                    );
+                    gl.uniform_1_u32(
+                        uniforms.glyph_columns.as_ref(),
+                        self.screen.chars_size[0] as u32,
+                    );
+                    gl.uniform_1_i32(uniforms.glyph_field.as_ref(), field);
+
+                    gl.draw_arrays_instanced(
+                        glow::TRIANGLE_STRIP,
+                        0,
+                        4,
+                        self.cell_cache.len() as i32,
+                    );
+                }
+                GlyphRendering::CellTexture => {
+                    gl.bind_vertex_array(Some(res.full_buf_verts));
+                    uniforms.grid_font.bind(gl.as_ref(), res.font_texture);
+                    uniforms.grid_cells.bind(gl.as_ref(), self.cell_texture);
+                    gl.use_program(Some(res.glyph_grid_program));
+                    gl.uniform_2_f32(
+                        uniforms.grid_origin.as_ref(),
+                        layout.origin[0],
+                        layout.origin[1],
+                    );
+                    gl.uniform_2_f32(
+                        uniforms.grid_cell_size.as_ref(),
+                        layout.cell_size[0],
+                        layout.cell_size[1],
+                    );
+                    gl.uniform_1_i32(uniforms.grid_field.as_ref(), field);
+
+                    gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+                }
+            }
+        }
     }
 
     pub fn screen_changed(&mut self, screen: ScreenInfo) {
         let gl = &self.gl;
+        let screen = ScreenInfo {
+            chars_size: screen.resolved_chars_size(),
+            ..screen
+        };
 
         unsafe {
             if self.screen.gl_size != screen.gl_size || self.screen.gl_pos != screen.gl_pos {
@@ -557,20 +1103,35 @@ impl<C: HasContext> CRTTerm<C> {
                 );
             }
 
-            if self.screen.frame_size != screen.frame_size {
+            if self.screen.raster_size() != screen.raster_size() {
                 gl.bind_texture(glow::TEXTURE_2D, Some(self.fade_texture));
                 gl.tex_image_2d(
                     glow::TEXTURE_2D,
                     0,
                     glow::RGB as i32,
-                    screen.frame_size[0] as i32,
-                    screen.frame_size[1] as i32,
+                    screen.raster_size()[0] as i32,
+                    screen.raster_size()[1] as i32,
+                    0,
+                    glow::RGB,
+                    glow::UNSIGNED_BYTE,
+                    None,
+                );
+
+                gl.bind_texture(glow::TEXTURE_2D, Some(self.composite_texture));
+                gl.tex_image_2d(
+                    glow::TEXTURE_2D,
+                    0,
+                    glow::RGB as i32,
+                    screen.raster_size()[0] as i32,
+                    screen.raster_size()[1] as i32,
                     0,
                     glow::RGB,
                     glow::UNSIGNED_BYTE,
                     None,
                 );
+            }
 
+            if self.screen.frame_size != screen.frame_size {
                 gl.bind_texture(glow::TEXTURE_2D, Some(self.effects_texture));
                 gl.tex_image_2d(
                     glow::TEXTURE_2D,
@@ -586,82 +1147,590 @@ impl<C: HasContext> CRTTerm<C> {
             }
 
             if self.screen.chars_size != screen.chars_size {
-                self.chars = (0..screen.chars_size[1])
-                    .map(|y| {
-                        (0..screen.chars_size[0])
-                            .map(|x| {
-                                self.chars
-                                    .get(y)
-                                    .and_then(|r| r.get(x))
-                                    .copied()
-                                    .unwrap_or('\0')
-                            })
-                            .collect::<Vec<_>>()
-                            .into_boxed_slice()
-                    })
-                    .collect::<Vec<_>>()
-                    .into_boxed_slice();
+                gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.cell_buf));
+                gl.buffer_data_size(
+                    glow::ARRAY_BUFFER,
+                    cell_buffer_size(screen.chars_size),
+                    glow::DYNAMIC_DRAW,
+                );
+                gl.bind_buffer(glow::ARRAY_BUFFER, None);
+
+                gl.bind_texture(glow::TEXTURE_2D, Some(self.cell_texture));
+                alloc_cell_texture(gl.as_ref(), screen.chars_size);
+
+                gl.bind_texture(glow::TEXTURE_2D, Some(self.text_texture));
+                alloc_text_texture(gl.as_ref(), screen.chars_size);
+
+                self.cell_cache = vec![[0; 4]; screen.chars_size[0] * screen.chars_size[1]];
+                self.dirty_rows = vec![true; screen.chars_size[1]];
+
+                self.reflow(screen.chars_size);
+                self.scroll_region = 0..screen.chars_size[1];
+                self.tab_stops = default_tab_stops(screen.chars_size[0]);
+                self.pending_wrap = false;
+
+                self.selection = None;
+                self.hover = None;
+                if let Some(search) = &mut self.search {
+                    search.mark_history_stale();
+                }
+                self.refresh_search();
             }
         }
 
+        if self.screen.glyph_rendering != screen.glyph_rendering {
+            self.dirty_rows.fill(true);
+        }
+
+        self.redraw_requested = true;
         self.screen = screen;
     }
 
-    fn scroll(&mut self) {
-        let mut buf = (0..self.screen.chars_size[0])
-            .map(|_| '\0')
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
-        for i in 1..self.screen.chars_size[1] {
-            buf.copy_from_slice(&self.chars[i]);
-            self.chars[i - 1].copy_from_slice(&buf);
+    /// Cursor position in the view and its shape, if it is shown, on screen
+    /// and not in the dark half of its blink cycle.
+    fn visible_cursor(&self, now: Instant) -> Option<([usize; 2], CursorShape)> {
+        let style = self.cursor_style.unwrap_or(self.screen.cursor_style);
+        let phase = now.saturating_duration_since(self.cursor_blink_start).as_nanos()
+            / CURSOR_BLINK_INTERVAL.as_nanos();
+        let row = self.cursor[1] + self.view_offset;
+        (self.modes.cursor_visible
+            && (!style.blinking || phase.is_multiple_of(2))
+            && row < self.screen.chars_size[1])
+            .then_some(([self.cursor[0], row], style.shape))
+    }
+
+    /// Text of `line`, counted from the top of the screen and going negative
+    /// into the scrollback.
+    fn line(&self, line: isize) -> Option<&[char]> {
+        history_line(&self.chars, &self.scrollback, line)
+    }
+
+    /// History position of the cell at `cell` in the current view.
+    fn view_point(&self, cell: [usize; 2]) -> Point {
+        Point {
+            line: cell[1] as isize - self.view_offset as isize,
+            col: cell[0],
+        }
+    }
+
+    fn selection_range(&self) -> Option<SelectionRange> {
+        self.selection?.range(|line| self.line(line))
+    }
+
+    /// Marks the visible rows covered by the current selection for re-upload.
+    fn mark_selection_dirty(&mut self) {
+        if let Some(range) = self.selection_range() {
+            for (y, dirty) in self.dirty_rows.iter_mut().enumerate() {
+                *dirty |= range.contains_line(y as isize - self.view_offset as isize);
+            }
         }
-        if let Some(last) = self.chars.last_mut() {
-            for char in last.iter_mut() {
-                *char = '\0'
+    }
+
+    /// Re-encodes the rows touched since the last frame and uploads each run of
+    /// consecutive dirty rows with a single call, to either the instance buffer
+    /// or the cell texture depending on the glyph rendering mode.
+    fn upload_dirty_rows(&mut self) {
+        let cols = self.screen.chars_size[0];
+        let selection = self.selection_range();
+        let cursor = self.drawn_cursor;
+        let hover = self.hover.as_ref();
+        let matches = self.search.as_ref().map_or(&[][..], |s| &s.matches);
+        let current_match = self.search.as_ref().and_then(|s| s.current_match());
+        let gl = &self.gl;
+
+        let mut y = 0;
+        while y < self.dirty_rows.len() {
+            if !self.dirty_rows[y] {
+                y += 1;
+                continue;
+            }
+
+            let start = y;
+            while y < self.dirty_rows.len() && self.dirty_rows[y] {
+                let line = y as isize - self.view_offset as isize;
+                let text = history_line(&self.chars, &self.scrollback, line).unwrap_or_default();
+                let links = history_line(&self.links, &self.scrollback_links, line)
+                    .unwrap_or_default();
+                let first = matches.partition_point(|m| m.line < line);
+                let last = matches.partition_point(|m| m.line <= line);
+                let line_matches = &matches[first..last];
+
+                let row = &mut self.cell_cache[y * cols..(y + 1) * cols];
+                for (col, cell) in row.iter_mut().enumerate() {
+                    let char = text.get(col).copied().unwrap_or('\0');
+                    let mut flags = 0;
+                    if selection.is_some_and(|s| s.contains(Point { line, col })) {
+                        flags |= CELL_SELECTED;
+                    }
+                    let link = links.get(col).copied().unwrap_or(0);
+                    if hover.is_some_and(|h| h.underlines(line, col, link)) {
+                        flags |= CELL_UNDERLINE;
+                    }
+                    if current_match.is_some_and(|m| m.contains(line, col)) {
+                        flags |= CELL_CURRENT_MATCH;
+                    } else if line_matches.iter().any(|m| m.contains(line, col)) {
+                        flags |= CELL_MATCH;
+                    }
+                    if let Some((_, shape)) = cursor.filter(|&(pos, _)| pos == [col, y]) {
+                        flags |= match shape {
+                            CursorShape::Block => CELL_CURSOR_BLOCK,
+                            CursorShape::Underline => CELL_CURSOR_UNDERLINE,
+                            CursorShape::Bar => CELL_CURSOR_BAR,
+                        };
+                    }
+                    *cell = encode_cell(char, flags);
+                }
+                self.dirty_rows[y] = false;
+                y += 1;
+            }
+
+            let data = cell_data_bytes(&self.cell_cache[start * cols..y * cols]);
+            unsafe {
+                match self.screen.glyph_rendering {
+                    GlyphRendering::Instanced => {
+                        gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.cell_buf));
+                        gl.buffer_sub_data_u8_slice(
+                            glow::ARRAY_BUFFER,
+                            (start * cols * std::mem::size_of::<CellData>()) as i32,
+                            data,
+                        );
+                        gl.bind_buffer(glow::ARRAY_BUFFER, None);
+                    }
+                    GlyphRendering::CellTexture => {
+                        gl.bind_texture(glow::TEXTURE_2D, Some(self.cell_texture));
+                        gl.tex_sub_image_2d(
+                            glow::TEXTURE_2D,
+                            0,
+                            0,
+                            start as i32,
+                            cols as i32,
+                            (y - start) as i32,
+                            glow::RGBA_INTEGER,
+                            glow::UNSIGNED_SHORT,
+                            glow::PixelUnpackData::Slice(data),
+                        );
+                    }
+                }
             }
         }
     }
+
+    /// Stores `c` at the cursor and advances it. At the last column the wrap
+    /// is left pending if autowrap is on, otherwise the character there is
+    /// overwritten by the next one.
+    fn print(&mut self, c: char) {
+        if self.pending_wrap {
+            if let Some(wrapped) = self.wrapped.get_mut(self.cursor[1]) {
+                *wrapped = true;
+            }
+            self.cursor[0] = 0;
+            self.line_feed();
+        }
+
+        let [x, y] = self.cursor;
+        if let Some(sym) = self.chars.get_mut(y).and_then(|row| row.get_mut(x)) {
+            *sym = c;
+            self.links[y][x] = self.active_link;
+            self.mark_dirty(y..y + 1);
+        }
+
+        if self.cursor[0] + 1 < self.screen.chars_size[0] {
+            self.cursor[0] += 1;
+        } else {
+            self.pending_wrap = self.modes.autowrap;
+        }
+    }
+
+    /// Moves the cursor down a row, scrolling the region at its bottom margin.
+    fn line_feed(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor[1] + 1 == self.scroll_region.end {
+            self.scroll_up(self.scroll_region.clone(), 1, true);
+        } else if self.cursor[1] + 1 < self.screen.chars_size[1] {
+            self.cursor[1] += 1;
+        }
+    }
+
+    /// Moves the cursor up a row, scrolling the region at its top margin.
+    fn reverse_line_feed(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor[1] == self.scroll_region.start {
+            self.scroll_down(self.scroll_region.clone(), 1);
+        } else {
+            self.cursor[1] = self.cursor[1].saturating_sub(1);
+        }
+    }
+
+    /// Moves the screen rows in `rows` up by `count`, blanking the rows that
+    /// open up at the bottom. With `save`, rows leaving the top of the primary
+    /// screen go into the scrollback.
+    fn scroll_up(&mut self, rows: Range<usize>, count: usize, save: bool) {
+        let rows = rows.start..rows.end.min(self.chars.len());
+        let count = count.min(rows.len());
+        if count == 0 {
+            return;
+        }
+
+        let save = save && rows.start == 0 && !self.alt_screen;
+        if save {
+            self.scrollback.extend(self.chars[..count].iter().cloned());
+            self.scrollback_links.extend(self.links[..count].iter().cloned());
+            self.scrollback_wrapped.extend(&self.wrapped[..count]);
+            let excess = self.scrollback.len().saturating_sub(SCROLLBACK_LINES);
+            self.scrollback.drain(..excess);
+            self.scrollback_links.drain(..excess);
+            self.scrollback_wrapped.drain(..excess);
+            if let Some(search) = &mut self.search {
+                search.shift(-(count as isize));
+            }
+
+            // keep a scrolled back view on the same text
+            if self.view_offset > 0 {
+                self.view_offset = (self.view_offset + count).min(self.scrollback.len());
+            }
+        }
+        self.shift_selection(rows.clone(), -(count as isize), save || self.alt_screen);
+
+        shift_rows(&mut self.chars, rows.clone(), count as isize);
+        shift_rows(&mut self.links, rows.clone(), count as isize);
+        let wrapped = &mut self.wrapped[rows.clone()];
+        wrapped.rotate_left(count);
+        wrapped[rows.len() - count..].fill(false);
+        self.mark_dirty(rows);
+    }
+
+    /// Moves the screen rows in `rows` down by `count`, blanking the rows that
+    /// open up at the top.
+    fn scroll_down(&mut self, rows: Range<usize>, count: usize) {
+        let rows = rows.start..rows.end.min(self.chars.len());
+        let count = count.min(rows.len());
+        if count == 0 {
+            return;
+        }
+
+        self.shift_selection(rows.clone(), count as isize, true);
+
+        shift_rows(&mut self.chars, rows.clone(), -(count as isize));
+        shift_rows(&mut self.links, rows.clone(), -(count as isize));
+        let wrapped = &mut self.wrapped[rows.clone()];
+        wrapped.rotate_right(count);
+        wrapped[..count].fill(false);
+        self.mark_dirty(rows);
+    }
+
+    /// Keeps the selection on its text when the screen rows in `rows` move by
+    /// `lines`. Only whole screen scrolls that keep the history consistent
+    /// (`follow`) can be followed, any other movement under the selection
+    /// drops it.
+    fn shift_selection(&mut self, rows: Range<usize>, lines: isize, follow: bool) {
+        let Some(range) = self.selection_range() else {
+            return;
+        };
+        let Some(selection) = &mut self.selection else {
+            return;
+        };
+
+        let oldest = if self.alt_screen {
+            0
+        } else {
+            -(self.scrollback.len() as isize)
+        };
+        let moved = rows.start as isize..rows.end as isize;
+
+        if follow && rows == (0..self.chars.len()) {
+            selection.shift(lines);
+            let [start, end] = [range.start.line + lines, range.end.line + lines];
+            if start < oldest || end >= moved.end {
+                self.selection = None;
+            }
+        } else if range.start.line < moved.end && moved.start <= range.end.line {
+            self.selection = None;
+        }
+    }
+
+    /// Marks screen rows for re-upload, taking the scrolled back view into account.
+    fn mark_dirty(&mut self, rows: Range<usize>) {
+        if let Some(search) = &mut self.search {
+            search.mark_stale(rows.clone());
+        }
+        let rows = rows.start + self.view_offset..rows.end + self.view_offset;
+        let len = self.dirty_rows.len();
+        self.dirty_rows[rows.start.min(len)..rows.end.min(len)].fill(true);
+    }
+}
+
+impl<C: HasContext> Drop for CRTTerm<C> {
+    fn drop(&mut self) {
+        let gl = &self.gl;
+        unsafe {
+            gl.delete_buffer(self.main_quad_buf);
+            gl.delete_buffer(self.cell_buf);
+            gl.delete_vertex_array(self.main_buf_verts);
+            gl.delete_vertex_array(self.glyph_verts);
+
+            gl.delete_texture(self.cell_texture);
+            gl.delete_texture(self.text_texture);
+            gl.delete_framebuffer(self.text_framebuffer);
+            gl.delete_texture(self.composite_texture);
+            gl.delete_framebuffer(self.composite_framebuffer);
+            gl.delete_texture(self.fade_texture);
+            gl.delete_framebuffer(self.fade_framebuffer);
+            gl.delete_texture(self.effects_texture);
+            gl.delete_framebuffer(self.effects_framebuffer);
+            gl.delete_buffer(self.effects_params_buf);
+        }
+    }
 }
 
+/// Writes text as if it came from the child process. Like a tty with `onlcr`
+/// set, `\n` starts a new line.
 impl<C: HasContext> std::fmt::Write for CRTTerm<C> {
     fn write_str(&mut self, s: &str) -> std::fmt::Result {
-        let err = s.chars().find_map(|c| self.write_char(c).err());
-        match err {
-            Some(e) => Err(e),
-            None => Ok(()),
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.process(b"\r\n");
+            }
+            self.process(line.as_bytes());
         }
+        Ok(())
     }
+}
 
-    fn write_char(&mut self, c: char) -> std::fmt::Result {
-        let mut cursor = self.cursor;
-        if !c.is_control() {
-            if let Some(row) = self.chars.get_mut(self.cursor[1]) {
-                if let Some(sym) = row.get_mut(self.cursor[0]) {
-                    *sym = c;
-                }
-            }
+/// CPU side of the `EffectParams` uniform block, laid out as std140.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct EffectParams {
+    pixel_size: [f32; 2],
+    time: f32,
+    _pad0: f32,
+    bg_color: [f32; 3],
+    _pad1: f32,
+    fg_color: [f32; 3],
+    rolling_scanline: f32,
+    raster_lines: f32,
+    mask_kind: f32,
+    mask_pitch: f32,
+    composite: f32,
+}
+
+impl EffectParams {
+    fn to_std140(self) -> [u8; std::mem::size_of::<EffectParams>()] {
+        unsafe { std::mem::transmute(self) }
+    }
+}
+
+fn color_to_f32(color: [u8; 3]) -> [f32; 3] {
+    color.map(|c| c as f32 / 255.0)
+}
+
+/// Placement of the character grid inside the fade framebuffer, in GL coordinates.
+#[derive(Debug, Clone, Copy)]
+struct GridLayout {
+    /// Top-left corner of the cell in column 0, row 0.
+    origin: [f32; 2],
+    /// Size of one cell including the spacing after the glyph.
+    cell_size: [f32; 2],
+    chars_size: [usize; 2],
+}
+
+impl GridLayout {
+    fn new(screen: &ScreenInfo) -> Self {
+        if screen.native_resolution {
+            return Self::upscaled(screen);
         }
-        cursor[0] += 1;
-        if c == '\n' || cursor[0] >= self.screen.chars_size[0] {
-            cursor[0] = 0;
-            cursor[1] += 1;
+
+        let gl_pos = [-1.0; 2];
+        let gl_size = [2.0; 2];
+        let chars_size = screen.chars_size;
+        let [cols, rows] = chars_size;
+
+        let char_bounds_w = gl_size[0] / cols as f32;
+
+        // remap char bounds to not include FONT_SPACING_X at the last column
+        let char_bounds_w = char_bounds_w * (cols * (FONT_CHAR_WIDTH + FONT_SPACING_X)) as f32
+            / (cols * FONT_CHAR_WIDTH + cols.saturating_sub(1) * FONT_SPACING_X) as f32;
+
+        let char_bounds_h = gl_size[1] / rows as f32;
+
+        let gl_offset_x = gl_size[0] * (1.0 - CRT_SCALE) * 0.5;
+        let gl_offset_y = gl_size[1] * (1.0 - CRT_SCALE) * 0.5;
+
+        Self {
+            origin: [gl_pos[0] + gl_offset_x, gl_pos[1] + gl_size[1] - gl_offset_y],
+            cell_size: [char_bounds_w * CRT_SCALE, char_bounds_h * CRT_SCALE],
+            chars_size,
         }
-        if cursor[1] >= self.screen.chars_size[1] {
-            cursor[0] = 0;
-            cursor[1] = self.screen.chars_size[1].max(1) - 1;
-            self.scroll();
+    }
+
+    /// Native resolution text scaled up by the largest whole factor that fits
+    /// inside the border, placed on whole raster pixels.
+    fn upscaled(screen: &ScreenInfo) -> Self {
+        let native = native_text_size(screen.chars_size).map(|s| s as f32);
+        let frame = screen.raster_size().map(|s| s.max(1) as f32);
+
+        let scale = (frame[0] * CRT_SCALE / native[0])
+            .min(frame[1] * CRT_SCALE / native[1])
+            .floor()
+            .max(1.0);
+        let offset = [0, 1].map(|i| ((frame[i] - native[i] * scale) / 2.0).floor());
+
+        Self {
+            origin: [offset[0] / frame[0] * 2.0 - 1.0, 1.0 - offset[1] / frame[1] * 2.0],
+            cell_size: [
+                (FONT_CHAR_WIDTH + FONT_SPACING_X) as f32 * scale / frame[0] * 2.0,
+                (FONT_CHAR_HEIGHT + FONT_SPACING_Y) as f32 * scale / frame[1] * 2.0,
+            ],
+            chars_size: screen.chars_size,
         }
-        self.cursor = cursor;
-        self.cursor_blinker = 60;
+    }
 
-        Ok(())
+    /// Grid filling the native resolution text texture.
+    fn native(chars_size: [usize; 2]) -> Self {
+        Self {
+            origin: [-1.0, 1.0],
+            cell_size: [2.0 / chars_size[0] as f32, 2.0 / chars_size[1] as f32],
+            chars_size,
+        }
+    }
+
+    /// Size of the whole grid.
+    fn size(&self) -> [f32; 2] {
+        [
+            self.cell_size[0] * self.chars_size[0] as f32,
+            self.cell_size[1] * self.chars_size[1] as f32,
+        ]
+    }
+
+    /// Cell containing the point `pos`, in the same coordinates as `origin`.
+    fn cell_at(&self, pos: [f32; 2]) -> Option<[usize; 2]> {
+        let x = (pos[0] - self.origin[0]) / self.cell_size[0];
+        let y = (self.origin[1] - pos[1]) / self.cell_size[1];
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+
+        let cell = [x as usize, y as usize];
+        (cell[0] < self.chars_size[0] && cell[1] < self.chars_size[1]).then_some(cell)
     }
 }
 
-fn add_glyph(buf: &mut Vec<u8>, char: char, pos: [f32; 2], size: [f32; 2]) {
-    let (uv_pos, uv_size) = get_font_glyph_uv(char);
-    buf.extend_from_slice(&create_quad_data_tris(pos, size, uv_pos, uv_size, true));
+/// Barrel distortion of the screen glass, mirroring `warp()` in `crt_warp.frag.glsl`.
+fn warp(uv: [f32; 2]) -> [f32; 2] {
+    let warp_amount = 0.3;
+
+    let delta = uv.map(|c| c - 0.5);
+    let delta2 = delta[0] * delta[0] + delta[1] * delta[1];
+    let delta4 = delta2 * delta2;
+    let delta_offset = delta4 * warp_amount;
+
+    [0, 1].map(|i| uv[i] + delta[i] * delta_offset)
+}
+
+/// One cell of the glyph instance buffer, and one texel of the cell texture:
+/// `[glyph, flags, color.rg, color.ba]`.
+type CellData = [u16; 4];
+
+fn encode_cell(char: char, flags: u16) -> CellData {
+    let color = u16::from_le_bytes([0xff, 0xff]);
+    [glyph_index(char), flags, color, color]
+}
+
+fn default_tab_stops(cols: usize) -> Vec<bool> {
+    (0..cols).map(|x| x.is_multiple_of(TAB_WIDTH)).collect()
+}
+
+/// Copy of `grid` truncated or padded with empty cells to `size`.
+fn resize_grid<T: Copy + Default>(grid: &[Box<[T]>], size: [usize; 2]) -> Box<[Box<[T]>]> {
+    (0..size[1])
+        .map(|y| {
+            (0..size[0])
+                .map(|x| grid.get(y).and_then(|r| r.get(x)).copied().unwrap_or_default())
+                .collect()
+        })
+        .collect()
+}
+
+/// Moves `rows` of `grid` up by `count`, or down if negative, blanking the
+/// rows that open up.
+fn shift_rows<T: Copy + Default>(grid: &mut [Box<[T]>], rows: Range<usize>, count: isize) {
+    let grid = &mut grid[rows];
+    let n = count.unsigned_abs().min(grid.len());
+    let blank = if count > 0 {
+        grid.rotate_left(n);
+        grid.len() - n..grid.len()
+    } else {
+        grid.rotate_right(n);
+        0..n
+    };
+    for row in &mut grid[blank] {
+        row.fill(T::default());
+    }
+}
+
+/// Row `line` of the history formed by `scrollback` followed by `screen`,
+/// with line 0 being the top of the screen.
+fn history_line<'a, T>(
+    screen: &'a [Box<[T]>],
+    scrollback: &'a VecDeque<Box<[T]>>,
+    line: isize,
+) -> Option<&'a [T]> {
+    if line >= 0 {
+        screen.get(line as usize).map(|row| &**row)
+    } else {
+        let index = scrollback.len().checked_sub(line.unsigned_abs())?;
+        scrollback.get(index).map(|row| &**row)
+    }
+}
+
+fn cell_data_bytes(cells: &[CellData]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(cells.as_ptr().cast(), std::mem::size_of_val(cells)) }
+}
+
+fn cell_buffer_size(chars_size: [usize; 2]) -> i32 {
+    (chars_size[0] * chars_size[1] * std::mem::size_of::<CellData>()) as i32
+}
+
+/// Size in texels of the text drawn at one texel per font pixel.
+fn native_text_size(chars_size: [usize; 2]) -> [i32; 2] {
+    [
+        (chars_size[0] * (FONT_CHAR_WIDTH + FONT_SPACING_X)) as i32,
+        (chars_size[1] * (FONT_CHAR_HEIGHT + FONT_SPACING_Y)) as i32,
+    ]
+}
+
+/// (Re)allocates storage of the currently bound native resolution text texture.
+fn alloc_text_texture<C: HasContext>(gl: &C, chars_size: [usize; 2]) {
+    let [width, height] = native_text_size(chars_size);
+    unsafe {
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA as i32,
+            width,
+            height,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            None,
+        );
+    }
+}
+
+/// (Re)allocates storage of the currently bound cell texture.
+fn alloc_cell_texture<C: HasContext>(gl: &C, chars_size: [usize; 2]) {
+    unsafe {
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA16UI as i32,
+            chars_size[0] as i32,
+            chars_size[1] as i32,
+            0,
+            glow::RGBA_INTEGER,
+            glow::UNSIGNED_SHORT,
+            None,
+        );
+    }
 }
 
 fn calc_quad_vertices(
@@ -694,7 +1763,7 @@ fn calc_quad_vertices(
 }
 
 // x y u v
-fn create_quad_data_tri_strip(
+pub(crate) fn create_quad_data_tri_strip(
     pos: [f32; 2],
     size: [f32; 2],
     uv_pos: [f32; 2],
@@ -704,68 +1773,11 @@ fn create_quad_data_tri_strip(
     unsafe { std::mem::transmute(calc_quad_vertices(pos, size, uv_pos, uv_size, flip_v)) }
 }
 
-fn create_quad_data_tris(
-    pos: [f32; 2],
-    size: [f32; 2],
-    uv_pos: [f32; 2],
-    uv_size: [f32; 2],
-    flip_v: bool,
-) -> [u8; 96] {
-    let [tl, tr, bl, br] = calc_quad_vertices(pos, size, uv_pos, uv_size, flip_v);
-    let verts = [tl, tr, bl, tr, bl, br];
-
-    unsafe { std::mem::transmute(verts) }
-}
-
-fn create_shader<C: HasContext>(gl: &C, source: &str, ty: u32) -> C::Shader {
-    unsafe {
-        let shader = gl.create_shader(ty).unwrap();
-        gl.shader_source(shader, source);
-        gl.compile_shader(shader);
-
-        if !gl.get_shader_compile_status(shader) {
-            let log = gl.get_shader_info_log(shader);
-            panic!("Could not compile shader type {ty}: {log}");
-        }
-
-        shader
+/// Index of `char` in the font atlas; `0` is an empty cell.
+fn glyph_index(char: char) -> u16 {
+    if (char as usize) < FONT_COLS * FONT_ROWS {
+        char as u16
+    } else {
+        b'?' as u16
     }
 }
-
-fn construct_program<C: HasContext>(gl: &C, program: C::Program, vert: &str, frag: &str) {
-    unsafe {
-        gl.attach_shader(program, create_shader(gl, vert, glow::VERTEX_SHADER));
-        gl.attach_shader(program, create_shader(gl, frag, glow::FRAGMENT_SHADER));
-        gl.link_program(program);
-
-        if !gl.get_program_link_status(program) {
-            let log = gl.get_program_info_log(program);
-            panic!("Could not link program: {log}");
-        }
-    }
-}
-
-/// returns: `([x, y], [w, h])`
-fn get_font_glyph_uv(char: char) -> ([f32; 2], [f32; 2]) {
-    const FONT_IMAGE_WIDTH: usize = FONT_COLS * (FONT_CHAR_WIDTH + FONT_IMAGE_SPACING_X);
-    const FONT_IMAGE_HEIGHT: usize = FONT_ROWS * (FONT_CHAR_HEIGHT + FONT_IMAGE_SPACING_Y);
-
-    const CHAR_UV_WIDTH: f32 = FONT_CHAR_WIDTH as f32 / FONT_IMAGE_WIDTH as f32;
-    const CHAR_UV_HEIGHT: f32 = FONT_CHAR_HEIGHT as f32 / FONT_IMAGE_HEIGHT as f32;
-
-    let ascii = if char.is_ascii() { char as u8 } else { 63 };
-
-    let col = ascii as usize % FONT_COLS;
-    let row = ascii as usize / FONT_COLS;
-
-    let x = col * (FONT_CHAR_WIDTH + FONT_IMAGE_SPACING_X);
-    let y = row * (FONT_CHAR_HEIGHT + FONT_IMAGE_SPACING_Y);
-
-    (
-        [
-            x as f32 / FONT_IMAGE_WIDTH as f32,
-            y as f32 / FONT_IMAGE_HEIGHT as f32,
-        ],
-        [CHAR_UV_WIDTH, CHAR_UV_HEIGHT],
-    )
-}
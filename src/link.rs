@@ -0,0 +1,331 @@
+use std::{ops::Range, sync::OnceLock};
+
+use glow::HasContext;
+use regex::Regex;
+
+use crate::{history_line, CRTTerm, TermEvent};
+
+/// Most OSC 8 links kept at once. Cell link ids are `u16` with 0 meaning no
+/// link, links started while every entry is in use are not recorded.
+const MAX_HYPERLINKS: usize = u16::MAX as usize;
+
+/// Target of a link in the terminal text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Link {
+    /// An OSC 8 hyperlink or a URL found in the text.
+    Url(String),
+    /// A `path:line` or `path:line:column` reference, as printed by compilers.
+    File {
+        path: String,
+        line: usize,
+        column: Option<usize>,
+    },
+}
+
+/// OSC 8 hyperlink, cells refer to it by its index plus one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Hyperlink {
+    /// The `id` parameter, which joins cells printed separately into one link.
+    pub(crate) id: Option<String>,
+    pub(crate) uri: String,
+}
+
+/// The cell under the pointer and the link there, which is underlined.
+#[derive(Debug, Clone)]
+pub(crate) struct Hover {
+    pub(crate) cell: [usize; 2],
+    /// OSC 8 link id of the hovered cell, 0 for a link found in the text or
+    /// no link at all.
+    pub(crate) id: u16,
+    /// History line and columns of a link found in the text, empty if none.
+    pub(crate) line: isize,
+    pub(crate) cols: Range<usize>,
+}
+
+impl Hover {
+    pub(crate) fn underlines(&self, line: isize, col: usize, id: u16) -> bool {
+        if self.id != 0 {
+            id == self.id
+        } else {
+            line == self.line && self.cols.contains(&col)
+        }
+    }
+}
+
+impl<C: HasContext> CRTTerm<C> {
+    /// Link at `cell`, a position on screen as returned by
+    /// [`CRTTerm::cell_at`]. OSC 8 hyperlinks take precedence over URLs and
+    /// file references found in the text of the row.
+    pub fn link_at(&self, cell: [usize; 2]) -> Option<Link> {
+        self.find_link(cell).map(|(_, _, link)| link)
+    }
+
+    /// Underlines the link under `cell`, or none when `None`. The host calls
+    /// this as the pointer moves.
+    pub fn hover(&mut self, cell: Option<[usize; 2]>) {
+        if self.hover.as_ref().map(|h| h.cell) != cell {
+            self.update_hover(cell);
+        }
+    }
+
+    /// Raises [`TermEvent::OpenLink`] for the link at `cell`, usually on
+    /// Ctrl+click. Returns whether there was a link.
+    pub fn open_link(&mut self, cell: [usize; 2]) -> bool {
+        match self.link_at(cell) {
+            Some(link) => {
+                self.push_event(TermEvent::OpenLink(link));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Looks up the link under the hovered cell again, after the text or the
+    /// view changed.
+    pub(crate) fn refresh_hover(&mut self) {
+        if let Some(hover) = &self.hover {
+            self.update_hover(Some(hover.cell));
+        }
+    }
+
+    fn update_hover(&mut self, cell: Option<[usize; 2]>) {
+        self.mark_hover_dirty();
+        self.hover = cell.map(|cell| {
+            let (id, cols) = self
+                .find_link(cell)
+                .map_or((0, 0..0), |(id, cols, _)| (id, cols));
+            Hover {
+                cell,
+                id,
+                line: self.view_point(cell).line,
+                cols,
+            }
+        });
+        self.mark_hover_dirty();
+    }
+
+    /// Marks the rows showing the hovered link for re-upload.
+    fn mark_hover_dirty(&mut self) {
+        let Some(hover) = &self.hover else {
+            return;
+        };
+        if hover.id != 0 {
+            // cells of an OSC 8 link can be anywhere on screen
+            self.dirty_rows.fill(true);
+        } else if let Some(dirty) = usize::try_from(hover.line + self.view_offset as isize)
+            .ok()
+            .and_then(|y| self.dirty_rows.get_mut(y))
+        {
+            *dirty = true;
+        }
+    }
+
+    /// OSC 8 id, columns of a detected link, and target of the link at `cell`.
+    fn find_link(&self, cell: [usize; 2]) -> Option<(u16, Range<usize>, Link)> {
+        let line = self.view_point(cell).line;
+
+        let id = history_line(&self.links, &self.scrollback_links, line)
+            .and_then(|ids| ids.get(cell[0]).copied())
+            .unwrap_or(0);
+        if id != 0 {
+            let hyperlink = self.hyperlinks.get(id as usize - 1)?.as_ref()?;
+            return Some((id, 0..0, Link::Url(hyperlink.uri.clone())));
+        }
+
+        let text = self.line(line)?;
+        detect_links(text)
+            .into_iter()
+            .find(|(cols, _)| cols.contains(&cell[0]))
+            .map(|(cols, link)| (0, cols, link))
+    }
+
+    /// Starts or, with an empty URI, ends the OSC 8 hyperlink given to the
+    /// characters printed next.
+    pub(crate) fn set_hyperlink(&mut self, params: &[u8], uri: &[u8]) {
+        if uri.is_empty() {
+            self.active_link = 0;
+            return;
+        }
+
+        let id = params
+            .split(|&b| b == b':')
+            .find_map(|param| param.strip_prefix(b"id="))
+            .map(|id| String::from_utf8_lossy(id).into_owned());
+        let uri = String::from_utf8_lossy(uri).into_owned();
+
+        // cells printed with the same id and URI belong to the same link, as
+        // do links without an id to the same URI
+        let hyperlink = Hyperlink { id, uri };
+        let existing = self.hyperlink_indices.get(&hyperlink).copied();
+        let index = existing.or_else(|| {
+            let free = self.free_hyperlink()?;
+            self.hyperlink_indices.insert(hyperlink.clone(), free);
+            self.hyperlinks[free] = Some(hyperlink);
+            Some(free)
+        });
+        self.active_link = index.map_or(0, |index| index as u16 + 1);
+    }
+
+    /// Index of an unused hyperlink entry, growing the table or, once it is
+    /// full, freeing the entries no cell refers to.
+    fn free_hyperlink(&mut self) -> Option<usize> {
+        if self.hyperlinks.len() < MAX_HYPERLINKS {
+            self.hyperlinks.push(None);
+            return Some(self.hyperlinks.len() - 1);
+        }
+        if let Some(free) = self.hyperlinks.iter().position(Option::is_none) {
+            return Some(free);
+        }
+
+        let mut used = vec![false; self.hyperlinks.len()];
+        let rows = self.links.iter().chain(self.other_links.iter()).chain(&self.scrollback_links);
+        for &id in rows.flat_map(|row| row.iter()) {
+            if id != 0 {
+                used[id as usize - 1] = true;
+            }
+        }
+        for (hyperlink, used) in self.hyperlinks.iter_mut().zip(used) {
+            if !used {
+                if let Some(freed) = hyperlink.take() {
+                    self.hyperlink_indices.remove(&freed);
+                }
+            }
+        }
+        self.hyperlinks.iter().position(Option::is_none)
+    }
+}
+
+/// URLs and file references in a row of text, with the columns they cover.
+fn detect_links(text: &[char]) -> Vec<(Range<usize>, Link)> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    static FILE: OnceLock<Regex> = OnceLock::new();
+    let url = URL.get_or_init(|| Regex::new(r#"\b(?:https?|ftp|file)://[^\s<>"'`]+"#).unwrap());
+    let file = FILE.get_or_init(|| {
+        Regex::new(r"(?:^|[\s(\[\x22'=])((?:[\w.~-]*/)*[\w.-]*\w\.\w+):(\d+)(?::(\d+))?").unwrap()
+    });
+
+    let text: String = text
+        .iter()
+        .map(|&c| if c == '\0' { ' ' } else { c })
+        .collect();
+    let col = |byte: usize| text[..byte].chars().count();
+
+    let mut links = Vec::new();
+    for m in url.find_iter(&text) {
+        let url = trim_url(m.as_str());
+        links.push((
+            col(m.start())..col(m.start() + url.len()),
+            Link::Url(url.to_owned()),
+        ));
+    }
+
+    for caps in file.captures_iter(&text) {
+        let (path, all) = (caps.get(1).unwrap(), caps.get(0).unwrap());
+        let cols = col(path.start())..col(all.end());
+        if links
+            .iter()
+            .any(|(c, _)| c.start < cols.end && cols.start < c.end)
+        {
+            continue;
+        }
+        let Ok(line) = caps[2].parse() else {
+            continue;
+        };
+        links.push((
+            cols,
+            Link::File {
+                path: path.as_str().to_owned(),
+                line,
+                column: caps.get(3).and_then(|c| c.as_str().parse().ok()),
+            },
+        ));
+    }
+
+    links
+}
+
+/// Drops punctuation that ends the sentence around a URL, and closing
+/// brackets without an opening one inside the URL.
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ':', ';', '!', '?', '\'', '"']);
+        let trimmed = match trimmed.chars().last() {
+            Some(close @ (')' | ']')) => {
+                let open = if close == ')' { '(' } else { '[' };
+                let depth = trimmed.matches(open).count();
+                if trimmed.matches(close).count() > depth {
+                    &trimmed[..trimmed.len() - 1]
+                } else {
+                    trimmed
+                }
+            }
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(text: &str) -> Vec<(Range<usize>, Link)> {
+        detect_links(&text.chars().collect::<Vec<_>>())
+    }
+
+    fn url(url: &str) -> Link {
+        Link::Url(url.to_owned())
+    }
+
+    fn file(path: &str, line: usize, column: Option<usize>) -> Link {
+        Link::File {
+            path: path.to_owned(),
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn url_trimming() {
+        assert_eq!(trim_url("https://a.org/x."), "https://a.org/x");
+        assert_eq!(trim_url("https://a.org/x?!\","), "https://a.org/x");
+        assert_eq!(trim_url("https://a.org/x)"), "https://a.org/x");
+        assert_eq!(trim_url("https://a.org/x)."), "https://a.org/x");
+        assert_eq!(trim_url("https://a.org/f(x)"), "https://a.org/f(x)");
+        assert_eq!(trim_url("https://a.org/[1]]"), "https://a.org/[1]");
+    }
+
+    #[test]
+    fn urls_in_text() {
+        assert_eq!(
+            links("see (https://a.org/wiki/Rust_(lang)), ok"),
+            [(5..35, url("https://a.org/wiki/Rust_(lang)"))]
+        );
+        // columns count characters, not bytes
+        assert_eq!(
+            links("\u{e9}\u{20ac} http://a.org/\u{e9}"),
+            [(3..17, url("http://a.org/\u{e9}"))]
+        );
+    }
+
+    #[test]
+    fn file_references() {
+        assert_eq!(
+            links("error at src/lib.rs:12:5: oops"),
+            [(9..24, file("src/lib.rs", 12, Some(5)))]
+        );
+        assert_eq!(links("(./main.c:7)"), [(1..11, file("./main.c", 7, None))]);
+        assert_eq!(links("at 12:30:00"), []);
+    }
+
+    #[test]
+    fn url_ports_are_not_files() {
+        assert_eq!(
+            links("http://host.org:8080/x"),
+            [(0..22, url("http://host.org:8080/x"))]
+        );
+    }
+}
@@ -1,15 +1,35 @@
-use std::{sync::Arc, fmt::Write};
+use std::{
+    path::Path,
+    process::Command,
+    sync::{mpsc::TryRecvError, Arc},
+    time::{Duration, Instant},
+};
 
-use crt_term_gl::ScreenInfo;
+use crt_term_gl::{
+    ClipboardPolicy, CursorStyle, GlyphRendering, GridSizing, Key, Link, Modifiers, MouseButton,
+    MouseEvent, ScreenInfo, SearchOptions, SelectionKind, TermEvent,
+};
 use glfw::Context;
 use glow::HasContext;
 
+mod pty;
+
+use pty::Pty;
+
+/// Shown while the shell has not set a title of its own.
+const WINDOW_TITLE: &str = "crt-term-gl";
+
+/// Clicks on the same cell closer together than this count as a double or triple click.
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Lines scrolled per notch of the mouse wheel.
+const WHEEL_LINES: f64 = 3.0;
 
 fn main() {
     let mut glfw = glfw::init::<()>(None).unwrap();
     glfw.set_swap_interval(glfw::SwapInterval::Sync(1));
     let (mut win, events) = glfw
-        .create_window(720, 405, "crt-term-gl", glfw::WindowMode::Windowed)
+        .create_window(720, 405, WINDOW_TITLE, glfw::WindowMode::Windowed)
         .unwrap();
 
     let gl =
@@ -23,50 +43,287 @@ fn main() {
             
         chars_size: [74, 29],
         frame_size: [0; 2],
+        sizing: GridSizing::CellSize([10.0, 14.0]),
 
         back_color: [0x0a, 0x22, 0x16],
         color: [0x30, 0xff, 0x80],
+
+        glyph_rendering: GlyphRendering::Instanced,
+        native_resolution: false,
+        raster_lines: None,
+        mask: None,
+        // the baseline look, at the cost of redrawing every frame
+        rolling_scanline: true,
+        interlaced: false,
+        composite: None,
+        cursor_style: CursorStyle::default(),
+        clipboard_policy: ClipboardPolicy::default(),
     };
 
+    let frame_size = [draw_size.0 as u32, draw_size.1 as u32];
     let mut crt = crt_term_gl::CRTTerm::new(
         gl.clone(),
         ScreenInfo {
-            frame_size: [draw_size.0 as u32, draw_size.1 as u32],
+            frame_size,
             ..default_screen_info
         },
     );
 
+    let mut pty = Pty::spawn(crt.chars_size(), frame_size).unwrap();
+    let output = pty.output().unwrap();
+
     unsafe { gl.clear_color(1.0, 1.0, 1.0, 1.0) };
     win.make_current();
     win.set_framebuffer_size_polling(true);
+    win.set_refresh_polling(true);
+    win.set_mouse_button_polling(true);
+    win.set_cursor_pos_polling(true);
+    win.set_scroll_polling(true);
+    win.set_key_polling(true);
+    win.set_char_mods_polling(true);
+
+    let mut selecting = false;
+    let mut last_click: Option<(Instant, [usize; 2])> = None;
+    let mut clicks = 0;
 
-    let string = "\
-Lorem ipsum dolor sit amet, consectetur adipiscing elit. Nam justo justo, aliquet vestibulum egestas sit amet, iaculis id enim. Mauris ullamcorper, ipsum eget ultricies congue, velit lacus varius libero, sed ultricies ex dui a eros. Cras id urna malesuada, fermentum nibh vitae, lacinia libero. Aliquam dui dui, tempus at lectus quis, posuere blandit velit. In sed varius sem, sit amet gravida justo. Proin ut ex massa. Phasellus sed dui semper, mollis ex in, elementum metus. Aenean dapibus augue interdum ante scelerisque aliquam. Aliquam erat volutpat. Nullam eleifend venenatis erat aliquet pretium.\n\
-\n\
-Curabitur gravida urna ut ligula aliquet vehicula. Curabitur viverra tortor eget mauris vestibulum, et pulvinar justo dapibus. Phasellus sed magna at libero gravida condimentum malesuada at felis. Sed elementum eget lacus porttitor hendrerit. Vivamus sodales at massa id auctor. Donec sed metus nec lorem porttitor porta sit amet non felis. Nam cursus consequat purus, vel tristique ipsum vivamus.";
-    let mut pos = 0;
+    // mouse state reported to applications that track the mouse
+    let mut reported_button: Option<MouseButton> = None;
+    let mut reported_cell: Option<[usize; 2]> = None;
+
+    // fraction of a line left over from touchpad scrolling
+    let mut wheel_lines = 0.0;
 
     while !win.should_close() {
-        glfw.poll_events();
+        match crt.next_wakeup() {
+            Some(wakeup) => {
+                let timeout = wakeup.saturating_duration_since(Instant::now());
+                if timeout.is_zero() {
+                    glfw.poll_events();
+                } else {
+                    glfw.wait_events_timeout(timeout.as_secs_f64());
+                }
+            }
+            None => glfw.wait_events(),
+        }
+
+        loop {
+            match output.try_recv() {
+                Ok(bytes) => crt.process(&bytes),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    win.set_should_close(true);
+                    break;
+                }
+            }
+        }
 
+        let mut input = Vec::new();
+        let mut exposed = false;
         for (_, event) in glfw::flush_messages(&events) {
-            if let glfw::WindowEvent::FramebufferSize(width, height) = event {
-                unsafe { gl.viewport(0, 0, width, height) };
-                crt.screen_changed(ScreenInfo {
-                    frame_size: [width as u32, height as u32],
-                    ..default_screen_info
-                },);
+            match event {
+                glfw::WindowEvent::FramebufferSize(width, height) => {
+                    unsafe { gl.viewport(0, 0, width, height) };
+                    let frame_size = [width as u32, height as u32];
+                    crt.screen_changed(ScreenInfo {
+                        frame_size,
+                        ..default_screen_info
+                    },);
+                    pty.resize(crt.chars_size(), frame_size);
+                }
+                glfw::WindowEvent::Refresh => exposed = true,
+                // Ctrl+click opens links, even when the application takes the mouse
+                glfw::WindowEvent::MouseButton(glfw::MouseButtonLeft, glfw::Action::Press, mods)
+                    if mods.contains(glfw::Modifiers::Control)
+                        && crt
+                            .cell_at(framebuffer_cursor_pos(&win))
+                            .is_some_and(|cell| crt.open_link(cell)) => {}
+                // Shift bypasses mouse reporting so text can still be selected
+                glfw::WindowEvent::MouseButton(button, action, mods)
+                    if crt.mouse_reporting()
+                        && !selecting
+                        && !mods.contains(glfw::Modifiers::Shift) =>
+                {
+                    let mut cell = crt.cell_at(framebuffer_cursor_pos(&win));
+                    // a release off the glass still ends the drag, at the last reported cell
+                    if action == glfw::Action::Release {
+                        cell = cell.or(reported_cell);
+                    }
+                    if let (Some(button), Some(cell)) = (map_mouse_button(button), cell) {
+                        let event = if action == glfw::Action::Press {
+                            reported_button = Some(button);
+                            MouseEvent::Press(button)
+                        } else {
+                            reported_button = None;
+                            MouseEvent::Release(button)
+                        };
+                        input.extend(crt.mouse_input(event, cell, map_modifiers(mods)));
+                        reported_cell = Some(cell);
+                    }
+                }
+                glfw::WindowEvent::CursorPos(..) if crt.mouse_reporting() && !selecting => {
+                    let cell = crt.cell_at(framebuffer_cursor_pos(&win));
+                    if let Some(cell) = cell.filter(|&cell| Some(cell) != reported_cell) {
+                        input.extend(crt.mouse_input(
+                            MouseEvent::Motion(reported_button),
+                            cell,
+                            current_modifiers(&win),
+                        ));
+                        reported_cell = Some(cell);
+                    }
+                }
+                glfw::WindowEvent::MouseButton(glfw::MouseButtonLeft, action, mods) => {
+                    let cell = crt.cell_at(framebuffer_cursor_pos(&win));
+                    match (action, cell) {
+                        (glfw::Action::Press, Some(cell)) => {
+                            let now = Instant::now();
+                            clicks = match last_click {
+                                Some((time, last))
+                                    if last == cell && now - time < MULTI_CLICK_INTERVAL =>
+                                {
+                                    clicks % 3 + 1
+                                }
+                                _ => 1,
+                            };
+                            last_click = Some((now, cell));
+
+                            let kind = if mods.contains(glfw::Modifiers::Alt) {
+                                SelectionKind::Block
+                            } else {
+                                match clicks {
+                                    2 => SelectionKind::Word,
+                                    3 => SelectionKind::Line,
+                                    _ => SelectionKind::Simple,
+                                }
+                            };
+                            crt.start_selection(cell, kind);
+                            selecting = true;
+                        }
+                        (glfw::Action::Press, None) => crt.clear_selection(),
+                        (glfw::Action::Release, _) => selecting = false,
+                        _ => {}
+                    }
+                }
+                glfw::WindowEvent::CursorPos(..) if selecting => {
+                    if let Some(cell) = crt.cell_at(framebuffer_cursor_pos(&win)) {
+                        crt.extend_selection(cell);
+                    }
+                }
+                glfw::WindowEvent::Scroll(_, y) if crt.mouse_reporting() => {
+                    if let Some(cell) = crt.cell_at(framebuffer_cursor_pos(&win)) {
+                        let button = if y > 0.0 {
+                            MouseButton::WheelUp
+                        } else {
+                            MouseButton::WheelDown
+                        };
+                        let event = MouseEvent::Press(button);
+                        for _ in 0..(y.abs().round() as usize).max(1) {
+                            input.extend(crt.mouse_input(event, cell, current_modifiers(&win)));
+                        }
+                    }
+                }
+                glfw::WindowEvent::Scroll(_, y) => {
+                    wheel_lines += y * WHEEL_LINES;
+                    let lines = wheel_lines.trunc();
+                    wheel_lines -= lines;
+                    crt.scroll_view(lines as isize);
+                }
+                glfw::WindowEvent::Key(glfw::Key::C, _, glfw::Action::Press, mods)
+                    if mods.contains(glfw::Modifiers::Control | glfw::Modifiers::Shift) =>
+                {
+                    if let Some(text) = crt.selected_text() {
+                        win.set_clipboard_string(&text);
+                    }
+                }
+                glfw::WindowEvent::Key(glfw::Key::V, _, glfw::Action::Press, mods)
+                    if mods.contains(glfw::Modifiers::Control | glfw::Modifiers::Shift) =>
+                {
+                    if let Some(text) = win.get_clipboard_string() {
+                        input.extend(crt.paste_input(&text));
+                    }
+                }
+                // search for the selected text, or end the search without a selection
+                glfw::WindowEvent::Key(glfw::Key::F, _, glfw::Action::Press, mods)
+                    if mods.contains(glfw::Modifiers::Control | glfw::Modifiers::Shift) =>
+                {
+                    match crt.selected_text().filter(|text| !text.contains('\n')) {
+                        Some(text) => {
+                            crt.clear_selection();
+                            let _ = crt.search(&text, SearchOptions::default());
+                            crt.search_previous();
+                        }
+                        None => crt.clear_search(),
+                    }
+                }
+                glfw::WindowEvent::Key(glfw::Key::N, _, glfw::Action::Press | glfw::Action::Repeat, mods)
+                    if mods.contains(glfw::Modifiers::Control | glfw::Modifiers::Shift) =>
+                {
+                    crt.search_next();
+                }
+                glfw::WindowEvent::Key(glfw::Key::P, _, glfw::Action::Press | glfw::Action::Repeat, mods)
+                    if mods.contains(glfw::Modifiers::Control | glfw::Modifiers::Shift) =>
+                {
+                    crt.search_previous();
+                }
+                glfw::WindowEvent::Key(glfw::Key::Insert, _, glfw::Action::Press, mods)
+                    if mods == glfw::Modifiers::Shift =>
+                {
+                    if let Some(text) = win.get_clipboard_string() {
+                        input.extend(crt.paste_input(&text));
+                    }
+                }
+                glfw::WindowEvent::Key(key, scancode, glfw::Action::Press | glfw::Action::Repeat, mods) => {
+                    if let Some(key) = map_key(key) {
+                        input.extend(crt.key_input(key, map_modifiers(mods)));
+                    } else if let Some(c) = modified_key_char(key, scancode, mods) {
+                        input.extend(crt.char_input(c, map_modifiers(mods)));
+                    }
+                }
+                // Ctrl+Shift is left to the shortcuts above
+                glfw::WindowEvent::CharModifiers(_, mods)
+                    if mods.contains(glfw::Modifiers::Control | glfw::Modifiers::Shift) => {}
+                // already sent from the key event, where it is not reported
+                // as text on every platform
+                glfw::WindowEvent::CharModifiers(c, mods) if is_modified_key_char(c, mods) => {}
+                glfw::WindowEvent::CharModifiers(c, mods) => {
+                    input.extend(crt.char_input(c, map_modifiers(mods)));
+                }
+                _ => {}
+            }
+        }
+
+        crt.hover(crt.cell_at(framebuffer_cursor_pos(&win)));
+
+        let mut responses = crt.take_responses();
+        for event in crt.take_events() {
+            match event {
+                TermEvent::Title(title) => {
+                    win.set_title(if title.is_empty() { WINDOW_TITLE } else { &title })
+                }
+                TermEvent::SetClipboard(text) => win.set_clipboard_string(&text),
+                TermEvent::QueryClipboard => {
+                    let text = win.get_clipboard_string().unwrap_or_default();
+                    responses.extend(crt.clipboard_input(&text));
+                }
+                TermEvent::OpenLink(link) => open_link(&link, pty.cwd().as_deref()),
+                TermEvent::IconName(_) => {}
             }
         }
 
-        if pos < string.len() {
-            let char = string[pos..].chars().next();
-            if let Some(char) = char {
-                let _ = crt.write_char(char);
-                pos += char.len_utf8();
+        if !responses.is_empty() && pty.write_all(&responses).is_err() {
+            win.set_should_close(true);
+        }
+
+        if !input.is_empty() {
+            crt.scroll_view(-(crt.view_offset() as isize));
+            if pty.write_all(&input).is_err() {
+                win.set_should_close(true);
             }
         }
 
+        if !exposed && !crt.needs_redraw() {
+            continue;
+        }
+
         unsafe { gl.clear(glow::COLOR_BUFFER_BIT) };
 
         crt.update();
@@ -74,3 +331,133 @@ Curabitur gravida urna ut ligula aliquet vehicula. Curabitur viverra tortor eget
         win.swap_buffers();
     }
 }
+
+/// Opens `link` with the desktop's default application. Relative file paths
+/// are taken from `cwd`, the working directory of the shell.
+fn open_link(link: &Link, cwd: Option<&Path>) {
+    let target = match link {
+        Link::Url(url) => url.into(),
+        Link::File { path, .. } => cwd.unwrap_or(Path::new("")).join(path),
+    };
+    // best effort, there is nothing to tell the user through
+    let _ = Command::new("xdg-open").arg(target).spawn();
+}
+
+/// Mouse position in framebuffer pixels, which differ from window coordinates
+/// on high-DPI displays.
+fn framebuffer_cursor_pos(win: &glfw::Window) -> [f32; 2] {
+    let (x, y) = win.get_cursor_pos();
+    let (win_w, win_h) = win.get_size();
+    let (fb_w, fb_h) = win.get_framebuffer_size();
+    [
+        (x * fb_w as f64 / win_w.max(1) as f64) as f32,
+        (y * fb_h as f64 / win_h.max(1) as f64) as f32,
+    ]
+}
+
+fn map_key(key: glfw::Key) -> Option<Key> {
+    use glfw::Key as G;
+
+    Some(match key {
+        G::Enter | G::KpEnter => Key::Enter,
+        G::Backspace => Key::Backspace,
+        G::Tab => Key::Tab,
+        G::Escape => Key::Escape,
+        G::Up => Key::Up,
+        G::Down => Key::Down,
+        G::Right => Key::Right,
+        G::Left => Key::Left,
+        G::Home => Key::Home,
+        G::End => Key::End,
+        G::Insert => Key::Insert,
+        G::Delete => Key::Delete,
+        G::PageUp => Key::PageUp,
+        G::PageDown => Key::PageDown,
+        G::F1 => Key::F(1),
+        G::F2 => Key::F(2),
+        G::F3 => Key::F(3),
+        G::F4 => Key::F(4),
+        G::F5 => Key::F(5),
+        G::F6 => Key::F(6),
+        G::F7 => Key::F(7),
+        G::F8 => Key::F(8),
+        G::F9 => Key::F(9),
+        G::F10 => Key::F(10),
+        G::F11 => Key::F(11),
+        G::F12 => Key::F(12),
+        _ => return None,
+    })
+}
+
+/// Character of a letter key, or a key that makes a control character, held
+/// with Ctrl or Alt. GLFW drops the control characters Ctrl gives on some
+/// platforms, so these are typed from the key event instead.
+fn modified_key_char(
+    key: glfw::Key,
+    scancode: glfw::Scancode,
+    mods: glfw::Modifiers,
+) -> Option<char> {
+    let ctrl = mods.contains(glfw::Modifiers::Control);
+    if !ctrl && !mods.contains(glfw::Modifiers::Alt) {
+        return None;
+    }
+    if key == glfw::Key::Space {
+        return ctrl.then_some(' ');
+    }
+
+    // the key as labeled in the current layout, or its US letter
+    let letter = (glfw::Key::A as i32..=glfw::Key::Z as i32).contains(&(key as i32));
+    let name = glfw::get_key_name(Some(key), Some(scancode))
+        .or_else(|| letter.then(|| (key as u8 as char).to_ascii_lowercase().to_string()))?;
+    let mut chars = name.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    match c {
+        'a'..='z' if mods.contains(glfw::Modifiers::Shift) => Some(c.to_ascii_uppercase()),
+        'a'..='z' => Some(c),
+        '[' | '\\' | ']' if ctrl => Some(c),
+        // xterm's Ctrl+2, Ctrl+6 and Ctrl+-
+        '2' if ctrl => Some('@'),
+        '6' if ctrl => Some('^'),
+        '-' if ctrl => Some('_'),
+        _ => None,
+    }
+}
+
+/// Whether a character event repeats what [`modified_key_char`] sent.
+fn is_modified_key_char(c: char, mods: glfw::Modifiers) -> bool {
+    let ctrl = mods.contains(glfw::Modifiers::Control);
+    (ctrl || mods.contains(glfw::Modifiers::Alt))
+        && (c.is_ascii_alphabetic() || ctrl && "[\\] @^_26-".contains(c))
+}
+
+fn map_mouse_button(button: glfw::MouseButton) -> Option<MouseButton> {
+    match button {
+        glfw::MouseButtonLeft => Some(MouseButton::Left),
+        glfw::MouseButtonMiddle => Some(MouseButton::Middle),
+        glfw::MouseButtonRight => Some(MouseButton::Right),
+        _ => None,
+    }
+}
+
+/// Modifiers held right now, for events that do not carry them.
+fn current_modifiers(win: &glfw::Window) -> Modifiers {
+    let held = |keys: [glfw::Key; 2]| {
+        keys.iter()
+            .any(|&key| win.get_key(key) != glfw::Action::Release)
+    };
+    Modifiers {
+        shift: held([glfw::Key::LeftShift, glfw::Key::RightShift]),
+        alt: held([glfw::Key::LeftAlt, glfw::Key::RightAlt]),
+        ctrl: held([glfw::Key::LeftControl, glfw::Key::RightControl]),
+    }
+}
+
+fn map_modifiers(mods: glfw::Modifiers) -> Modifiers {
+    Modifiers {
+        shift: mods.contains(glfw::Modifiers::Shift),
+        alt: mods.contains(glfw::Modifiers::Alt),
+        ctrl: mods.contains(glfw::Modifiers::Control),
+    }
+}
@@ -0,0 +1,366 @@
+/// Longest OSC payload that is kept, large enough for OSC 52 clipboard data.
+const MAX_OSC_LEN: usize = 1 << 20;
+const MAX_PARAMS: usize = 32;
+
+/// Text and control functions decoded from the output of the child process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Action {
+    Print(char),
+    /// A C0 control character such as `\r` or `\n`.
+    Execute(u8),
+    Esc(Esc),
+    Csi(Csi),
+    /// Payload of an OSC string, without the introducer and terminator.
+    Osc(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Esc {
+    pub(crate) intermediates: Vec<u8>,
+    pub(crate) final_byte: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Csi {
+    /// Private marker in front of the parameters, like the `?` of DECSET.
+    pub(crate) private: Option<u8>,
+    pub(crate) params: Vec<u16>,
+    pub(crate) intermediates: Vec<u8>,
+    pub(crate) final_byte: u8,
+}
+
+impl Csi {
+    /// Parameter at `index`, with missing and zero values replaced by `default`.
+    pub(crate) fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params.get(index) {
+            Some(&p) if p != 0 => p,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    Osc,
+    OscEscape,
+    /// DCS, SOS, PM and APC strings, which are skipped.
+    String,
+    StringEscape,
+}
+
+/// Byte level state machine after the DEC/ECMA-48 model, decoding UTF-8 text
+/// in the ground state.
+#[derive(Debug, Default)]
+pub(crate) struct Parser {
+    state: State,
+
+    private: Option<u8>,
+    params: Vec<u16>,
+    param: Option<u16>,
+    intermediates: Vec<u8>,
+    ignore: bool,
+
+    osc: Vec<u8>,
+
+    utf8: Vec<u8>,
+    utf8_len: usize,
+}
+
+impl Parser {
+    pub(crate) fn advance(&mut self, byte: u8, perform: &mut impl FnMut(Action)) {
+        // CAN and SUB abort any sequence, ESC starts a new one
+        match (byte, self.state) {
+            (0x18 | 0x1a, _) => {
+                self.state = State::Ground;
+                return;
+            }
+            (0x1b, State::Ground | State::Escape | State::Csi) => {
+                self.utf8.clear();
+                self.enter(State::Escape);
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => self.ground(byte, perform),
+            State::Escape => match byte {
+                0x00..=0x1f => perform(Action::Execute(byte)),
+                0x20..=0x2f => self.intermediates.push(byte),
+                b'[' if self.intermediates.is_empty() => self.enter(State::Csi),
+                b']' if self.intermediates.is_empty() => self.enter(State::Osc),
+                b'P' | b'X' | b'^' | b'_' if self.intermediates.is_empty() => {
+                    self.enter(State::String)
+                }
+                0x30..=0x7e => {
+                    perform(Action::Esc(Esc {
+                        intermediates: std::mem::take(&mut self.intermediates),
+                        final_byte: byte,
+                    }));
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::Csi => match byte {
+                0x00..=0x1f => perform(Action::Execute(byte)),
+                b'0'..=b'9' => {
+                    let digit = (byte - b'0') as u16;
+                    let param = self.param.unwrap_or(0);
+                    self.param = Some(param.saturating_mul(10).saturating_add(digit));
+                }
+                b';' | b':' => self.push_param(),
+                b'<'..=b'?' => {
+                    if self.private.is_none() && self.params.is_empty() && self.param.is_none() {
+                        self.private = Some(byte);
+                    } else {
+                        self.ignore = true;
+                    }
+                }
+                0x20..=0x2f => self.intermediates.push(byte),
+                0x40..=0x7e => {
+                    if self.param.is_some() || !self.params.is_empty() {
+                        self.push_param();
+                    }
+                    if !self.ignore {
+                        perform(Action::Csi(Csi {
+                            private: self.private,
+                            params: std::mem::take(&mut self.params),
+                            intermediates: std::mem::take(&mut self.intermediates),
+                            final_byte: byte,
+                        }));
+                    }
+                    self.state = State::Ground;
+                }
+                _ => self.ignore = true,
+            },
+            State::Osc => match byte {
+                0x07 => {
+                    perform(Action::Osc(std::mem::take(&mut self.osc)));
+                    self.state = State::Ground;
+                }
+                0x1b => self.state = State::OscEscape,
+                _ if self.osc.len() < MAX_OSC_LEN => self.osc.push(byte),
+                _ => {}
+            },
+            State::OscEscape => {
+                perform(Action::Osc(std::mem::take(&mut self.osc)));
+                self.enter(State::Escape);
+                if byte != b'\\' {
+                    self.advance(byte, perform);
+                } else {
+                    self.state = State::Ground;
+                }
+            }
+            State::String => {
+                if byte == 0x1b {
+                    self.state = State::StringEscape;
+                }
+            }
+            State::StringEscape => {
+                self.enter(State::Escape);
+                if byte != b'\\' {
+                    self.advance(byte, perform);
+                } else {
+                    self.state = State::Ground;
+                }
+            }
+        }
+    }
+
+    fn enter(&mut self, state: State) {
+        self.state = state;
+        self.private = None;
+        self.params.clear();
+        self.param = None;
+        self.intermediates.clear();
+        self.ignore = false;
+        self.osc.clear();
+    }
+
+    fn push_param(&mut self) {
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(self.param.take().unwrap_or(0));
+        } else {
+            self.ignore = true;
+        }
+        self.param = None;
+    }
+
+    fn ground(&mut self, byte: u8, perform: &mut impl FnMut(Action)) {
+        if !self.utf8.is_empty() {
+            if byte & 0xc0 == 0x80 {
+                self.utf8.push(byte);
+                if self.utf8.len() == self.utf8_len {
+                    let char = std::str::from_utf8(&self.utf8)
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.utf8.clear();
+                    perform(Action::Print(char));
+                }
+                return;
+            }
+
+            // truncated sequence, the byte starts something new
+            self.utf8.clear();
+            perform(Action::Print(char::REPLACEMENT_CHARACTER));
+        }
+
+        match byte {
+            0x00..=0x1f => perform(Action::Execute(byte)),
+            0x20..=0x7e => perform(Action::Print(byte as char)),
+            0x7f => {}
+            0xc2..=0xf4 => {
+                self.utf8_len = match byte {
+                    0xc2..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    _ => 4,
+                };
+                self.utf8.push(byte);
+            }
+            _ => perform(Action::Print(char::REPLACEMENT_CHARACTER)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Vec<Action> {
+        let mut parser = Parser::default();
+        let mut actions = Vec::new();
+        for &byte in bytes {
+            parser.advance(byte, &mut |action| actions.push(action));
+        }
+        actions
+    }
+
+    fn csi(private: Option<u8>, params: &[u16], final_byte: u8) -> Action {
+        Action::Csi(Csi {
+            private,
+            params: params.to_vec(),
+            intermediates: Vec::new(),
+            final_byte,
+        })
+    }
+
+    #[test]
+    fn text_and_controls() {
+        assert_eq!(
+            parse("a\u{e9}\u{20ac}\r\n".as_bytes()),
+            [
+                Action::Print('a'),
+                Action::Print('\u{e9}'),
+                Action::Print('\u{20ac}'),
+                Action::Execute(b'\r'),
+                Action::Execute(b'\n'),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(
+            parse(b"\xe2\x82a\xff"),
+            [
+                Action::Print(char::REPLACEMENT_CHARACTER),
+                Action::Print('a'),
+                Action::Print(char::REPLACEMENT_CHARACTER),
+            ]
+        );
+    }
+
+    #[test]
+    fn csi_params() {
+        assert_eq!(parse(b"\x1b[m"), [csi(None, &[], b'm')]);
+        assert_eq!(parse(b"\x1b[1;;31m"), [csi(None, &[1, 0, 31], b'm')]);
+        assert_eq!(parse(b"\x1b[?1049h"), [csi(Some(b'?'), &[1049], b'h')]);
+        assert_eq!(parse(b"\x1b[99999A"), [csi(None, &[u16::MAX], b'A')]);
+        assert_eq!(
+            parse(b"\x1b[2 q"),
+            [Action::Csi(Csi {
+                private: None,
+                params: vec![2],
+                intermediates: vec![b' '],
+                final_byte: b'q',
+            })]
+        );
+    }
+
+    #[test]
+    fn malformed_csi_is_ignored() {
+        assert_eq!(parse(b"\x1b[1?2hx"), [Action::Print('x')]);
+
+        let mut many = b"\x1b[".to_vec();
+        for _ in 0..=MAX_PARAMS {
+            many.extend(b"1;");
+        }
+        many.extend(b"mx");
+        assert_eq!(parse(&many), [Action::Print('x')]);
+    }
+
+    #[test]
+    fn controls_inside_csi() {
+        assert_eq!(
+            parse(b"\x1b[1\n2H"),
+            [Action::Execute(b'\n'), csi(None, &[12], b'H')]
+        );
+    }
+
+    #[test]
+    fn aborted_sequences() {
+        assert_eq!(parse(b"\x1b[12\x18x"), [Action::Print('x')]);
+        assert_eq!(parse(b"\x1b[12\x1b[3m"), [csi(None, &[3], b'm')]);
+    }
+
+    #[test]
+    fn esc_sequences() {
+        assert_eq!(
+            parse(b"\x1b(0\x1b7"),
+            [
+                Action::Esc(Esc {
+                    intermediates: vec![b'('],
+                    final_byte: b'0',
+                }),
+                Action::Esc(Esc {
+                    intermediates: Vec::new(),
+                    final_byte: b'7',
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_terminators() {
+        let title = Action::Osc(b"0;title".to_vec());
+        assert_eq!(parse(b"\x1b]0;title\x07"), vec![title.clone()]);
+        assert_eq!(
+            parse(b"\x1b]0;title\x1b\\x"),
+            [title.clone(), Action::Print('x')]
+        );
+        // an escape that is not ST ends the string and starts a new sequence
+        assert_eq!(parse(b"\x1b]0;title\x1b[m"), [title, csi(None, &[], b'm')]);
+    }
+
+    #[test]
+    fn osc_length_limit() {
+        let mut bytes = b"\x1b]".to_vec();
+        bytes.resize(MAX_OSC_LEN + 100, b'a');
+        bytes.push(0x07);
+        match parse(&bytes).as_slice() {
+            [Action::Osc(payload)] => assert_eq!(payload.len(), MAX_OSC_LEN),
+            actions => panic!("unexpected {:?}", actions.len()),
+        }
+    }
+
+    #[test]
+    fn skipped_strings() {
+        assert_eq!(parse(b"\x1bPq#0;1\x07x\x1b\\y"), [Action::Print('y')]);
+        assert_eq!(parse(b"\x1b_apc\x1b\\\x1b[m"), [csi(None, &[], b'm')]);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    ffi::{CString, OsStr},
+    fs::File,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{ffi::OsStrExt, fs::PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the shell gets to exit after SIGHUP before it is killed.
+const HANGUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A shell running on a pseudo terminal.
+pub struct Pty {
+    master: File,
+    pid: libc::pid_t,
+}
+
+impl Pty {
+    /// Starts `$SHELL`, or `/bin/sh`, on a new pseudo terminal of `chars_size`
+    /// cells covering `frame_size` pixels.
+    pub fn spawn(chars_size: [usize; 2], frame_size: [u32; 2]) -> io::Result<Self> {
+        let shell = std::env::var_os("SHELL").unwrap_or_else(|| "/bin/sh".into());
+        let shell = find_program(&shell)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "shell not found"))?;
+        let shell = CString::new(shell.as_os_str().as_bytes())?;
+
+        // everything the child needs is allocated here, as only
+        // async-signal-safe calls may follow the fork in a threaded process
+        let mut env = std::env::vars_os()
+            .filter(|(name, _)| name != "TERM")
+            .map(|(name, value)| CString::new([name.as_bytes(), b"=", value.as_bytes()].concat()))
+            .collect::<Result<Vec<_>, _>>()?;
+        env.push(CString::new("TERM=xterm-256color")?);
+        let envp: Vec<_> = env
+            .iter()
+            .map(|var| var.as_ptr())
+            .chain([std::ptr::null()])
+            .collect();
+        let argv = [shell.as_ptr(), std::ptr::null()];
+        let empty_mask = unsafe {
+            let mut mask = std::mem::zeroed();
+            libc::sigemptyset(&mut mask);
+            mask
+        };
+
+        let mut master = -1;
+        let size = winsize(chars_size, frame_size);
+        let pid = unsafe {
+            libc::forkpty(&mut master, std::ptr::null_mut(), std::ptr::null(), &size)
+        };
+
+        match pid {
+            -1 => Err(io::Error::last_os_error()),
+            0 => unsafe {
+                // the Rust runtime ignores SIGPIPE, which would be inherited
+                // by every pipeline the shell runs
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                libc::sigprocmask(libc::SIG_SETMASK, &empty_mask, std::ptr::null_mut());
+                libc::execve(shell.as_ptr(), argv.as_ptr(), envp.as_ptr());
+                libc::_exit(127)
+            },
+            pid => Ok(Self {
+                master: unsafe { File::from_raw_fd(master) },
+                pid,
+            }),
+        }
+    }
+
+    pub fn resize(&self, chars_size: [usize; 2], frame_size: [u32; 2]) {
+        let size = winsize(chars_size, frame_size);
+        unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+    }
+
+    /// Current working directory of the shell.
+    pub fn cwd(&self) -> Option<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/cwd", self.pid)).ok()
+    }
+
+    pub fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)
+    }
+
+    /// Reads the output of the shell on a background thread. The channel
+    /// disconnects when the shell exits, and every chunk wakes up the GLFW
+    /// event loop.
+    pub fn output(&self) -> io::Result<mpsc::Receiver<Vec<u8>>> {
+        let mut master = self.master.try_clone()?;
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(n @ 1..) = master.read(&mut buf) {
+                if sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+                glfw::Glfw::post_empty_event();
+            }
+            drop(sender);
+            glfw::Glfw::post_empty_event();
+        });
+
+        Ok(receiver)
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        // the shell or a job it started may ignore the hangup
+        let deadline = Instant::now() + HANGUP_TIMEOUT;
+        unsafe {
+            libc::kill(self.pid, libc::SIGHUP);
+            while libc::waitpid(self.pid, std::ptr::null_mut(), libc::WNOHANG) == 0 {
+                if Instant::now() >= deadline {
+                    libc::kill(self.pid, libc::SIGKILL);
+                    libc::waitpid(self.pid, std::ptr::null_mut(), 0);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+/// Path of the executable `name`, looked up in `$PATH` unless it has a slash.
+fn find_program(name: &OsStr) -> Option<PathBuf> {
+    if name.as_bytes().contains(&b'/') {
+        return Some(name.into());
+    }
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+fn winsize(chars_size: [usize; 2], frame_size: [u32; 2]) -> libc::winsize {
+    libc::winsize {
+        ws_row: chars_size[1] as u16,
+        ws_col: chars_size[0] as u16,
+        ws_xpixel: frame_size[0] as u16,
+        ws_ypixel: frame_size[1] as u16,
+    }
+}
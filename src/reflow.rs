@@ -0,0 +1,226 @@
+use glow::HasContext;
+
+use crate::{resize_grid, CRTTerm, SCROLLBACK_LINES};
+
+/// Text printed as one line, before it was wrapped into rows.
+#[derive(Default)]
+struct LogicalLine {
+    chars: Vec<char>,
+    links: Vec<u16>,
+}
+
+/// Rows of a reflowed screen or history.
+#[derive(Default)]
+struct Rows {
+    chars: Vec<Box<[char]>>,
+    links: Vec<Box<[u16]>>,
+    wrapped: Vec<bool>,
+}
+
+impl<C: HasContext> CRTTerm<C> {
+    /// Rewraps the primary screen and the scrollback to `size`, joining rows
+    /// that were wrapped by printing past the last column and splitting them
+    /// again at the new width. The cursor stays on the character it was on,
+    /// and rows that no longer fit on screen go into the scrollback.
+    ///
+    /// The alternate screen is only cut or padded, as the full-screen
+    /// programs using it redraw everything after a resize.
+    pub(crate) fn reflow(&mut self, size: [usize; 2]) {
+        let [cols, rows] = size;
+        let cols = cols.max(1);
+
+        let (screen, links, wrapped, cursor) = if self.alt_screen {
+            (&self.other_chars, &self.other_links, &self.other_wrapped, self.saved_cursors[0])
+        } else {
+            (&self.chars, &self.links, &self.wrapped, self.cursor)
+        };
+
+        // empty rows below the cursor are not kept
+        let used = screen
+            .iter()
+            .rposition(|row| row.iter().any(|&c| c != '\0'))
+            .map_or(0, |y| y + 1)
+            .max(cursor[1] + 1)
+            .min(screen.len());
+
+        let history =
+            self.scrollback.iter().zip(&self.scrollback_links).zip(&self.scrollback_wrapped);
+        let screen = screen.iter().zip(links.iter()).zip(wrapped).take(used);
+        let old_rows = history
+            .chain(screen)
+            .map(|((chars, links), &wrapped)| (&**chars, &**links, wrapped));
+        let cursor = [cursor[0], self.scrollback.len() + cursor[1]];
+        let (mut new_rows, mut new_cursor) = rewrap(old_rows, cursor, cols);
+
+        // the screen shows the last rows, or those from the cursor down if
+        // there is more below it than fits
+        let top = new_rows.chars.len().saturating_sub(rows).min(new_cursor[1]);
+        let history_start = top.saturating_sub(SCROLLBACK_LINES);
+        self.scrollback = new_rows.chars.drain(..top).skip(history_start).collect();
+        self.scrollback_links = new_rows.links.drain(..top).skip(history_start).collect();
+        self.scrollback_wrapped = new_rows.wrapped.drain(..top).skip(history_start).collect();
+        new_cursor[1] -= top;
+
+        let screen = resize_grid(&new_rows.chars, size);
+        let links = resize_grid(&new_rows.links, size);
+        let mut wrapped = new_rows.wrapped;
+        wrapped.resize(rows, false);
+
+        if self.alt_screen {
+            self.other_chars = screen;
+            self.other_links = links;
+            self.other_wrapped = wrapped;
+            self.saved_cursors[0] = new_cursor;
+
+            self.chars = resize_grid(&self.chars, size);
+            self.links = resize_grid(&self.links, size);
+            self.wrapped = vec![false; rows];
+            self.cursor = [self.cursor[0].min(cols - 1), self.cursor[1].min(rows.saturating_sub(1))];
+        } else {
+            self.chars = screen;
+            self.links = links;
+            self.wrapped = wrapped;
+            self.cursor = new_cursor;
+        }
+        self.view_offset = 0;
+    }
+}
+
+/// Joins `rows` that were wrapped into logical lines and splits them again at
+/// `cols`. `cursor` is a column and an index into `rows`, it is returned as
+/// the same in the new rows.
+fn rewrap<'a>(
+    rows: impl Iterator<Item = (&'a [char], &'a [u16], bool)>,
+    cursor: [usize; 2],
+    cols: usize,
+) -> (Rows, [usize; 2]) {
+    let mut lines: Vec<LogicalLine> = Vec::new();
+    // logical line and offset in it of the cursor
+    let mut cursor_at = (0, 0);
+    let mut continued = false;
+    for (i, (chars, links, wrapped)) in rows.enumerate() {
+        if !continued {
+            lines.push(LogicalLine::default());
+        }
+        let index = lines.len() - 1;
+        let line = &mut lines[index];
+        if i == cursor[1] {
+            cursor_at = (index, line.chars.len() + cursor[0]);
+        }
+        line.chars.extend_from_slice(chars);
+        line.links.extend_from_slice(links);
+        continued = wrapped;
+    }
+
+    let mut new_rows = Rows::default();
+    let mut new_cursor = [0, 0];
+    for (i, mut line) in lines.into_iter().enumerate() {
+        let len = line.chars.iter().rposition(|&c| c != '\0').map_or(0, |x| x + 1);
+        line.chars.truncate(len);
+        line.links.truncate(len);
+
+        let mut count = len.div_ceil(cols).max(1);
+        if i == cursor_at.0 {
+            let offset = cursor_at.1;
+            count = count.max(offset / cols + 1);
+            new_cursor = [offset % cols, new_rows.chars.len() + offset / cols];
+        }
+
+        for r in 0..count {
+            let cells = r * cols..(r + 1) * cols;
+            let chars = cells.clone().map(|x| line.chars.get(x).copied().unwrap_or('\0'));
+            new_rows.chars.push(chars.collect());
+            new_rows.links.push(cells.map(|x| line.links.get(x).copied().unwrap_or(0)).collect());
+            new_rows.wrapped.push(r + 1 < count);
+        }
+    }
+    (new_rows, new_cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows of `cols` cells from text, `+` at the end marks a wrapped row.
+    fn rows(text: &[&str], cols: usize) -> Vec<(Vec<char>, Vec<u16>, bool)> {
+        text.iter()
+            .map(|row| {
+                let (row, wrapped) = match row.strip_suffix('+') {
+                    Some(row) => (row, true),
+                    None => (*row, false),
+                };
+                let mut chars: Vec<char> = row.chars().collect();
+                chars.resize(cols, '\0');
+                (chars, vec![0; cols], wrapped)
+            })
+            .collect()
+    }
+
+    fn rewrap_text(
+        text: &[&str],
+        old_cols: usize,
+        cursor: [usize; 2],
+        cols: usize,
+    ) -> (Vec<String>, [usize; 2]) {
+        let old = rows(text, old_cols);
+        let old = old
+            .iter()
+            .map(|(chars, links, wrapped)| (&chars[..], &links[..], *wrapped));
+        let (new, cursor) = rewrap(old, cursor, cols);
+        let text = new
+            .chars
+            .iter()
+            .zip(&new.wrapped)
+            .map(|(row, &wrapped)| {
+                let row: String = row.iter().filter(|&&c| c != '\0').collect();
+                if wrapped {
+                    row + "+"
+                } else {
+                    row
+                }
+            })
+            .collect();
+        (text, cursor)
+    }
+
+    #[test]
+    fn wrapped_lines_are_joined() {
+        assert_eq!(
+            rewrap_text(&["abcd+", "efgh+", "ij", "$ x"], 4, [3, 3], 6),
+            (vec!["abcdef+".into(), "ghij".into(), "$ x".into()], [3, 2])
+        );
+    }
+
+    #[test]
+    fn wrapped_lines_are_split() {
+        assert_eq!(
+            rewrap_text(&["abcdefgh", "$ "], 8, [2, 1], 3),
+            (
+                vec!["abc+".into(), "def+".into(), "gh".into(), "$ ".into()],
+                [2, 3]
+            )
+        );
+    }
+
+    #[test]
+    fn hard_breaks_are_kept() {
+        assert_eq!(
+            rewrap_text(&["ab", "cd", ""], 4, [0, 2], 8),
+            (vec!["ab".into(), "cd".into(), "".into()], [0, 2])
+        );
+    }
+
+    #[test]
+    fn cursor_stays_on_its_character() {
+        // on the 'f' of a line wrapped at 4, then at 3
+        assert_eq!(
+            rewrap_text(&["abcd+", "efg"], 4, [1, 1], 3),
+            (vec!["abc+".into(), "def+".into(), "g".into()], [2, 1])
+        );
+        // past the end of the text, the line grows to keep the cursor on it
+        assert_eq!(
+            rewrap_text(&["ab"], 8, [6, 0], 4),
+            (vec!["ab+".into(), "".into()], [2, 1])
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use std::sync::Arc;
+
+use glow::HasContext;
+
+use crate::{
+    create_quad_data_tri_strip, CELL_CURRENT_MATCH, CELL_CURSOR_BAR, CELL_CURSOR_BLOCK,
+    CELL_CURSOR_UNDERLINE, CELL_MATCH, CELL_SELECTED, CELL_UNDERLINE, FONT_5X11,
+    FONT_CHAR_HEIGHT, FONT_CHAR_WIDTH, FONT_COLS, FONT_IMAGE_SPACING_X, FONT_IMAGE_SPACING_Y,
+    FONT_SPACING_X, FONT_SPACING_Y,
+};
+
+const VERT_SHADER: &str = r#"#version 330 core
+  layout (location = 0) in vec2 pos;
+  layout (location = 1) in vec2 uv_in;
+
+  out vec2 uv;
+  void main() {
+    gl_Position = vec4(pos.x, pos.y, 0.0, 1.0);
+    uv = uv_in;
+  }
+"#;
+
+const CRT_WARP_FRAG_SHADER: &str = include_str!("crt_warp.frag.glsl");
+const CRT_FADING_FRAG_SHADER: &str = include_str!("crt_fading.frag.glsl");
+const CRT_EFFECTS_SHADER: &str = include_str!("crt_effects.frag.glsl");
+const TEXT_UPSCALE_FRAG_SHADER: &str = include_str!("text_upscale.frag.glsl");
+const COMPOSITE_FRAG_SHADER: &str = include_str!("composite.frag.glsl");
+
+/// Font metrics and cell flags shared with the glyph shaders, so they are only
+/// defined once.
+const GLYPH_SHADER_HEADER: &str = const_str::concat!(
+    "#version 330 core\n",
+    "const uint CELL_SELECTED = ", CELL_SELECTED, "u;\n",
+    "const uint CELL_CURSOR_BLOCK = ", CELL_CURSOR_BLOCK, "u;\n",
+    "const uint CELL_CURSOR_UNDERLINE = ", CELL_CURSOR_UNDERLINE, "u;\n",
+    "const uint CELL_CURSOR_BAR = ", CELL_CURSOR_BAR, "u;\n",
+    "const uint CELL_UNDERLINE = ", CELL_UNDERLINE, "u;\n",
+    "const uint CELL_MATCH = ", CELL_MATCH, "u;\n",
+    "const uint CELL_CURRENT_MATCH = ", CELL_CURRENT_MATCH, "u;\n",
+    "const uint FONT_COLS = ", FONT_COLS, "u;\n",
+    "const ivec2 FONT_CHAR_SIZE = ivec2(", FONT_CHAR_WIDTH, ", ", FONT_CHAR_HEIGHT, ");\n",
+    "const ivec2 FONT_IMAGE_CELL_SIZE = ivec2(",
+    FONT_CHAR_WIDTH + FONT_IMAGE_SPACING_X, ", ",
+    FONT_CHAR_HEIGHT + FONT_IMAGE_SPACING_Y, ");\n",
+    "const vec2 FONT_CELL_SIZE = vec2(",
+    FONT_CHAR_WIDTH + FONT_SPACING_X, ", ",
+    FONT_CHAR_HEIGHT + FONT_SPACING_Y, ");\n",
+);
+const GLYPH_VERT_SHADER: &str =
+    const_str::concat!(GLYPH_SHADER_HEADER, include_str!("glyph.vert.glsl"));
+const GLYPH_FRAG_SHADER: &str = const_str::concat!(
+    GLYPH_SHADER_HEADER,
+    include_str!("glyph_common.glsl"),
+    include_str!("glyph.frag.glsl"),
+);
+const GLYPH_GRID_FRAG_SHADER: &str = const_str::concat!(
+    GLYPH_SHADER_HEADER,
+    include_str!("glyph_common.glsl"),
+    include_str!("glyph_grid.frag.glsl"),
+);
+
+/// Uniform buffer binding point of the `EffectParams` block in `crt_effects.frag.glsl`.
+pub(crate) const EFFECT_PARAMS_BINDING: u32 = 0;
+
+/// GPU state that does not depend on any particular terminal: the font atlas,
+/// the compiled programs and the static quad buffers.
+///
+/// Create it once per GL context and hand it to [`crate::CRTTerm::with_resources`]
+/// so that any number of terminals can share it.
+pub struct CrtResources<C: HasContext> {
+    pub(crate) gl: Arc<C>,
+
+    pub(crate) full_quad_buf: C::Buffer,
+    pub(crate) full_buf_verts: C::VertexArray,
+    pub(crate) glyph_quad_buf: C::Buffer,
+
+    pub(crate) glyph_program: C::Program,
+    pub(crate) glyph_grid_program: C::Program,
+    pub(crate) crt_fading_program: C::Program,
+    pub(crate) crt_warp_program: C::Program,
+    pub(crate) crt_effects_program: C::Program,
+    pub(crate) text_upscale_program: C::Program,
+    pub(crate) composite_program: C::Program,
+
+    pub(crate) font_texture: C::Texture,
+
+    pub(crate) uniforms: UniformTable<C>,
+}
+
+impl<C: HasContext> CrtResources<C> {
+    pub fn new(gl: Arc<C>) -> Self {
+        let font_image =
+            image::load_from_memory_with_format(FONT_5X11, image::ImageFormat::Png).unwrap();
+        let font_image = font_image.into_rgba8();
+
+        let full_quad_buf = unsafe { gl.create_buffer().unwrap() };
+        let full_buf_verts = unsafe { gl.create_vertex_array().unwrap() };
+        let glyph_quad_buf = unsafe { gl.create_buffer().unwrap() };
+
+        let glyph_program = unsafe { gl.create_program().unwrap() };
+        let glyph_grid_program = unsafe { gl.create_program().unwrap() };
+        let crt_warp_program = unsafe { gl.create_program().unwrap() };
+        let crt_fading_program = unsafe { gl.create_program().unwrap() };
+        let crt_effects_program = unsafe { gl.create_program().unwrap() };
+        let text_upscale_program = unsafe { gl.create_program().unwrap() };
+        let composite_program = unsafe { gl.create_program().unwrap() };
+
+        let font_texture = unsafe { gl.create_texture().unwrap() };
+
+        unsafe {
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(full_quad_buf));
+            gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                &create_quad_data_tri_strip(
+                    [-1.0, -1.0],
+                    [2.0, 2.0],
+                    [0.0, 0.0],
+                    [1.0, 1.0],
+                    false,
+                ),
+                glow::STATIC_DRAW,
+            );
+
+            gl.bind_vertex_array(Some(full_buf_verts));
+            gl.vertex_attrib_pointer_f32(0, 2, glow::FLOAT, false, 16, 0);
+            gl.vertex_attrib_pointer_f32(1, 2, glow::FLOAT, false, 16, 8);
+            gl.enable_vertex_attrib_array(0);
+            gl.enable_vertex_attrib_array(1);
+            gl.bind_vertex_array(None);
+
+            gl.bind_buffer(glow::ARRAY_BUFFER, Some(glyph_quad_buf));
+            gl.buffer_data_u8_slice(
+                glow::ARRAY_BUFFER,
+                &create_quad_data_tri_strip(
+                    [0.0, 0.0],
+                    [1.0, 1.0],
+                    [0.0, 0.0],
+                    [1.0, 1.0],
+                    false,
+                ),
+                glow::STATIC_DRAW,
+            );
+            gl.bind_buffer(glow::ARRAY_BUFFER, None);
+
+            construct_program(
+                gl.as_ref(),
+                crt_warp_program,
+                VERT_SHADER,
+                CRT_WARP_FRAG_SHADER,
+            );
+            construct_program(
+                gl.as_ref(),
+                crt_fading_program,
+                VERT_SHADER,
+                CRT_FADING_FRAG_SHADER,
+            );
+            construct_program(
+                gl.as_ref(),
+                crt_effects_program,
+                VERT_SHADER,
+                CRT_EFFECTS_SHADER,
+            );
+            construct_program(
+                gl.as_ref(),
+                text_upscale_program,
+                VERT_SHADER,
+                TEXT_UPSCALE_FRAG_SHADER,
+            );
+            construct_program(
+                gl.as_ref(),
+                composite_program,
+                VERT_SHADER,
+                COMPOSITE_FRAG_SHADER,
+            );
+            construct_program(
+                gl.as_ref(),
+                glyph_program,
+                GLYPH_VERT_SHADER,
+                GLYPH_FRAG_SHADER,
+            );
+            construct_program(
+                gl.as_ref(),
+                glyph_grid_program,
+                VERT_SHADER,
+                GLYPH_GRID_FRAG_SHADER,
+            );
+
+            gl.bind_texture(glow::TEXTURE_2D, Some(font_texture));
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA as i32,
+                font_image.width() as i32,
+                font_image.height() as i32,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                Some(font_image.as_raw()),
+            );
+            gl.generate_mipmap(glow::TEXTURE_2D);
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::NEAREST as i32,
+            );
+        }
+
+        let uniforms = UniformTable {
+            glyph_font: Sampler::new(gl.as_ref(), glyph_program, "font", 0),
+            glyph_origin: unsafe { gl.get_uniform_location(glyph_program, "gridOrigin") },
+            glyph_cell_size: unsafe { gl.get_uniform_location(glyph_program, "cellSize") },
+            glyph_columns: unsafe { gl.get_uniform_location(glyph_program, "columns") },
+            glyph_field: unsafe { gl.get_uniform_location(glyph_program, "field") },
+            grid_font: Sampler::new(gl.as_ref(), glyph_grid_program, "font", 0),
+            grid_cells: Sampler::new(gl.as_ref(), glyph_grid_program, "cells", 1),
+            grid_origin: unsafe { gl.get_uniform_location(glyph_grid_program, "gridOrigin") },
+            grid_cell_size: unsafe { gl.get_uniform_location(glyph_grid_program, "cellSize") },
+            grid_field: unsafe { gl.get_uniform_location(glyph_grid_program, "field") },
+            fading_frame: Sampler::new(gl.as_ref(), crt_fading_program, "frame", 0),
+            effects_frame: Sampler::new(gl.as_ref(), crt_effects_program, "frame", 0),
+            warp_tex: Sampler::new(gl.as_ref(), crt_warp_program, "tex", 0),
+            upscale_text: Sampler::new(gl.as_ref(), text_upscale_program, "text", 0),
+            upscale_origin: unsafe { gl.get_uniform_location(text_upscale_program, "textOrigin") },
+            upscale_size: unsafe { gl.get_uniform_location(text_upscale_program, "textSize") },
+            upscale_field: unsafe { gl.get_uniform_location(text_upscale_program, "field") },
+            composite_frame: Sampler::new(gl.as_ref(), composite_program, "frame", 0),
+            composite_standard: unsafe { gl.get_uniform_location(composite_program, "standard") },
+            composite_frame_count: unsafe {
+                gl.get_uniform_location(composite_program, "frameCount")
+            },
+        };
+        bind_uniform_block(
+            gl.as_ref(),
+            crt_effects_program,
+            "EffectParams",
+            EFFECT_PARAMS_BINDING,
+        );
+
+        Self {
+            gl,
+
+            full_quad_buf,
+            full_buf_verts,
+            glyph_quad_buf,
+
+            glyph_program,
+            glyph_grid_program,
+            crt_fading_program,
+            crt_warp_program,
+            crt_effects_program,
+            text_upscale_program,
+            composite_program,
+
+            font_texture,
+
+            uniforms,
+        }
+    }
+}
+
+impl<C: HasContext> Drop for CrtResources<C> {
+    fn drop(&mut self) {
+        let gl = &self.gl;
+        unsafe {
+            gl.delete_buffer(self.full_quad_buf);
+            gl.delete_vertex_array(self.full_buf_verts);
+            gl.delete_buffer(self.glyph_quad_buf);
+
+            gl.delete_program(self.glyph_program);
+            gl.delete_program(self.glyph_grid_program);
+            gl.delete_program(self.crt_fading_program);
+            gl.delete_program(self.crt_warp_program);
+            gl.delete_program(self.crt_effects_program);
+            gl.delete_program(self.text_upscale_program);
+            gl.delete_program(self.composite_program);
+
+            gl.delete_texture(self.font_texture);
+        }
+    }
+}
+
+/// Uniform locations of every program, resolved once after linking so that
+/// rendering never has to query the driver.
+pub(crate) struct UniformTable<C: HasContext> {
+    pub(crate) glyph_font: Sampler,
+    pub(crate) glyph_origin: Option<C::UniformLocation>,
+    pub(crate) glyph_cell_size: Option<C::UniformLocation>,
+    pub(crate) glyph_columns: Option<C::UniformLocation>,
+    pub(crate) glyph_field: Option<C::UniformLocation>,
+    pub(crate) grid_font: Sampler,
+    pub(crate) grid_cells: Sampler,
+    pub(crate) grid_origin: Option<C::UniformLocation>,
+    pub(crate) grid_cell_size: Option<C::UniformLocation>,
+    pub(crate) grid_field: Option<C::UniformLocation>,
+    pub(crate) fading_frame: Sampler,
+    pub(crate) effects_frame: Sampler,
+    pub(crate) warp_tex: Sampler,
+    pub(crate) upscale_text: Sampler,
+    pub(crate) upscale_origin: Option<C::UniformLocation>,
+    pub(crate) upscale_size: Option<C::UniformLocation>,
+    pub(crate) upscale_field: Option<C::UniformLocation>,
+    pub(crate) composite_frame: Sampler,
+    pub(crate) composite_standard: Option<C::UniformLocation>,
+    pub(crate) composite_frame_count: Option<C::UniformLocation>,
+}
+
+/// A sampler uniform pinned to a texture unit when its program is linked.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sampler {
+    unit: u32,
+}
+
+impl Sampler {
+    fn new<C: HasContext>(gl: &C, program: C::Program, name: &str, unit: u32) -> Self {
+        unsafe {
+            let location = gl.get_uniform_location(program, name);
+            gl.use_program(Some(program));
+            gl.uniform_1_i32(location.as_ref(), unit as i32);
+            gl.use_program(None);
+        }
+
+        Self { unit }
+    }
+
+    pub(crate) fn bind<C: HasContext>(self, gl: &C, texture: C::Texture) {
+        unsafe {
+            gl.active_texture(glow::TEXTURE0 + self.unit);
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+        }
+    }
+}
+
+fn bind_uniform_block<C: HasContext>(gl: &C, program: C::Program, name: &str, binding: u32) {
+    unsafe {
+        if let Some(index) = gl.get_uniform_block_index(program, name) {
+            gl.uniform_block_binding(program, index, binding);
+        }
+    }
+}
+
+fn create_shader<C: HasContext>(gl: &C, source: &str, ty: u32) -> C::Shader {
+    unsafe {
+        let shader = gl.create_shader(ty).unwrap();
+        gl.shader_source(shader, source);
+        gl.compile_shader(shader);
+
+        if !gl.get_shader_compile_status(shader) {
+            let log = gl.get_shader_info_log(shader);
+            panic!("Could not compile shader type {ty}: {log}");
+        }
+
+        shader
+    }
+}
+
+fn construct_program<C: HasContext>(gl: &C, program: C::Program, vert: &str, frag: &str) {
+    unsafe {
+        gl.attach_shader(program, create_shader(gl, vert, glow::VERTEX_SHADER));
+        gl.attach_shader(program, create_shader(gl, frag, glow::FRAGMENT_SHADER));
+        gl.link_program(program);
+
+        if !gl.get_program_link_status(program) {
+            let log = gl.get_program_info_log(program);
+            panic!("Could not link program: {log}");
+        }
+    }
+}
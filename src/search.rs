@@ -0,0 +1,286 @@
+use std::ops::Range;
+
+use glow::HasContext;
+use regex::{Regex, RegexBuilder};
+
+use crate::CRTTerm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchOptions {
+    /// Treat the pattern as a regular expression instead of plain text.
+    pub regex: bool,
+    pub case_sensitive: bool,
+}
+
+/// Text matching a search, within one row of the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// Row counted from the top of the screen, going negative into the
+    /// scrollback.
+    pub line: isize,
+    /// First column of the match.
+    pub start: usize,
+    /// Column after the last one of the match.
+    pub end: usize,
+}
+
+impl SearchMatch {
+    pub(crate) fn contains(&self, line: isize, col: usize) -> bool {
+        line == self.line && (self.start..self.end).contains(&col)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Search {
+    regex: Regex,
+    /// Matches in history order, oldest first.
+    pub(crate) matches: Vec<SearchMatch>,
+    /// Index of the match navigated to.
+    pub(crate) current: Option<usize>,
+    /// Screen rows written since the matches were found.
+    stale_rows: Vec<bool>,
+    /// The searched history changed as a whole, as on a screen switch.
+    stale_history: bool,
+}
+
+impl Search {
+    /// Moves the matches by `lines`, used when rows scroll into the scrollback.
+    pub(crate) fn shift(&mut self, lines: isize) {
+        for m in &mut self.matches {
+            m.line += lines;
+        }
+    }
+
+    pub(crate) fn current_match(&self) -> Option<SearchMatch> {
+        self.current.map(|i| self.matches[i])
+    }
+
+    /// Notes that the screen rows in `rows` need searching again.
+    pub(crate) fn mark_stale(&mut self, rows: Range<usize>) {
+        let len = self.stale_rows.len();
+        self.stale_rows[rows.start.min(len)..rows.end.min(len)].fill(true);
+    }
+
+    /// Notes that the whole history needs searching again.
+    pub(crate) fn mark_history_stale(&mut self) {
+        self.stale_history = true;
+    }
+}
+
+impl<C: HasContext> CRTTerm<C> {
+    /// Finds `pattern` in the screen and the scrollback and highlights every
+    /// match. Matches do not span rows. The search stays active, following
+    /// new output, until [`CRTTerm::clear_search`].
+    pub fn search(
+        &mut self,
+        pattern: &str,
+        options: SearchOptions,
+    ) -> Result<&[SearchMatch], regex::Error> {
+        let pattern = if options.regex {
+            pattern.to_owned()
+        } else {
+            regex::escape(pattern)
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!options.case_sensitive)
+            .build()?;
+
+        self.mark_search_dirty();
+        self.search = Some(Search {
+            matches: self.find_matches(&regex, self.oldest_line()..self.chars.len() as isize),
+            regex,
+            current: None,
+            stale_rows: vec![false; self.chars.len()],
+            stale_history: false,
+        });
+        self.mark_search_dirty();
+
+        Ok(self.search_matches())
+    }
+
+    pub fn clear_search(&mut self) {
+        self.mark_search_dirty();
+        self.search = None;
+    }
+
+    /// Matches of the active search, oldest first.
+    pub fn search_matches(&self) -> &[SearchMatch] {
+        self.search.as_ref().map_or(&[], |s| &s.matches)
+    }
+
+    /// Goes to the next newer match, starting from the top of the view, and
+    /// scrolls it into view. Wraps around after the newest match.
+    pub fn search_next(&mut self) -> Option<SearchMatch> {
+        let top = -(self.view_offset as isize);
+        self.go_to_match(|search| {
+            let len = search.matches.len();
+            match search.current {
+                Some(i) => (i + 1) % len,
+                None => search.matches.iter().position(|m| m.line >= top).unwrap_or(0),
+            }
+        })
+    }
+
+    /// Goes to the next older match, starting from the bottom of the view,
+    /// and scrolls it into view. Wraps around after the oldest match.
+    pub fn search_previous(&mut self) -> Option<SearchMatch> {
+        let bottom = self.screen.chars_size[1] as isize - self.view_offset as isize;
+        self.go_to_match(|search| {
+            let len = search.matches.len();
+            match search.current {
+                Some(i) => (i + len - 1) % len,
+                None => search.matches.iter().rposition(|m| m.line < bottom).unwrap_or(len - 1),
+            }
+        })
+    }
+
+    /// Searches the rows that changed again, keeping the current match if it
+    /// is still there.
+    pub(crate) fn refresh_search(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let oldest = self.oldest_line();
+        let trimmed = search.matches.first().is_some_and(|m| m.line < oldest);
+        if !search.stale_history && !trimmed && !search.stale_rows.contains(&true) {
+            return;
+        }
+
+        let current = search.current_match();
+        let matches = if search.stale_history {
+            self.find_matches(&search.regex, oldest..self.chars.len() as isize)
+        } else {
+            let stale = |line: isize| {
+                usize::try_from(line).is_ok_and(|y| search.stale_rows.get(y) == Some(&true))
+            };
+            let mut matches: Vec<_> = search
+                .matches
+                .iter()
+                .copied()
+                .filter(|m| m.line >= oldest && !stale(m.line))
+                .collect();
+            for (y, _) in search.stale_rows.iter().enumerate().filter(|(_, &s)| s) {
+                matches.extend(self.find_matches(&search.regex, y as isize..y as isize + 1));
+            }
+            matches.sort_by_key(|m| (m.line, m.start));
+            matches
+        };
+
+        self.mark_search_dirty();
+        let rows = self.chars.len();
+        if let Some(search) = &mut self.search {
+            search.current = current.and_then(|c| matches.iter().position(|&m| m == c));
+            search.matches = matches;
+            search.stale_rows = vec![false; rows];
+            search.stale_history = false;
+        }
+        self.mark_search_dirty();
+    }
+
+    fn go_to_match(&mut self, index: impl FnOnce(&Search) -> usize) -> Option<SearchMatch> {
+        let search = self.search.as_mut().filter(|s| !s.matches.is_empty())?;
+        self.dirty_rows.fill(true);
+        search.current = Some(index(search));
+        let current = search.current_match()?;
+
+        // center matches outside the view
+        let rows = self.screen.chars_size[1] as isize;
+        let y = current.line + self.view_offset as isize;
+        if !(0..rows).contains(&y) {
+            let offset = rows / 2 - current.line;
+            self.scroll_view(offset - self.view_offset as isize);
+        }
+
+        Some(current)
+    }
+
+    /// Oldest line of the searched history, the alternate screen has none.
+    fn oldest_line(&self) -> isize {
+        if self.alt_screen {
+            0
+        } else {
+            -(self.scrollback.len() as isize)
+        }
+    }
+
+    /// All matches of `regex` in the history `lines`, oldest first.
+    fn find_matches(&self, regex: &Regex, lines: Range<isize>) -> Vec<SearchMatch> {
+        let mut matches = Vec::new();
+        for line in lines {
+            let row = self.line(line).unwrap_or_default();
+            matches.extend(match_columns(regex, row).into_iter().map(|cols| SearchMatch {
+                line,
+                start: cols.start,
+                end: cols.end,
+            }));
+        }
+        matches
+    }
+
+    /// Marks the visible rows with matches for re-upload.
+    fn mark_search_dirty(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        for m in &search.matches {
+            if let Some(dirty) = usize::try_from(m.line + self.view_offset as isize)
+                .ok()
+                .and_then(|y| self.dirty_rows.get_mut(y))
+            {
+                *dirty = true;
+            }
+        }
+    }
+}
+
+/// Column ranges of the matches of `regex` in `row`.
+fn match_columns(regex: &Regex, row: &[char]) -> Vec<Range<usize>> {
+    let text: String = row.iter().map(|&c| if c == '\0' { ' ' } else { c }).collect();
+    // byte offset of every column, for mapping matches back to cells
+    let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let col = |byte: usize| offsets.partition_point(|&offset| offset < byte);
+
+    regex
+        .find_iter(&text)
+        .filter(|m| !m.is_empty())
+        .map(|m| col(m.start())..col(m.end()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(pattern: &str, row: &str) -> Vec<[usize; 2]> {
+        let row: Vec<char> = row.chars().collect();
+        match_columns(&Regex::new(pattern).unwrap(), &row)
+            .into_iter()
+            .map(|cols| [cols.start, cols.end])
+            .collect()
+    }
+
+    #[test]
+    fn ascii_columns() {
+        assert_eq!(columns("ab", "xab ab"), [[1, 3], [4, 6]]);
+    }
+
+    #[test]
+    fn multibyte_columns() {
+        assert_eq!(
+            columns("caf\u{e9}", "\u{20ac}1 caf\u{e9} caf\u{e9}"),
+            [[3, 7], [8, 12]]
+        );
+        assert_eq!(columns("\u{1f600}+", "a\u{1f600}\u{1f600}b"), [[1, 3]]);
+        assert_eq!(columns("b$", "\u{e9}\u{e9}b"), [[2, 3]]);
+    }
+
+    #[test]
+    fn empty_cells_match_spaces() {
+        assert_eq!(columns("a b", "a\0b"), [[0, 3]]);
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        assert!(columns("x*", "ab").is_empty());
+    }
+}
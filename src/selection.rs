@@ -0,0 +1,154 @@
+/// How a selection grows from the cell where it was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionKind {
+    /// Every character between the press and the pointer.
+    Simple,
+    /// The rectangle spanned by the press and the pointer, usually on Alt-drag.
+    Block,
+    /// Whole words, usually on double click.
+    Word,
+    /// Whole lines, usually on triple click.
+    Line,
+}
+
+/// Position in the terminal history. `line` counts rows from the top of the
+/// screen and goes negative into the scrollback, so points stay attached to
+/// their text while the view is scrolled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Point {
+    pub(crate) line: isize,
+    pub(crate) col: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Selection {
+    pub(crate) kind: SelectionKind,
+    pub(crate) anchor: Point,
+    pub(crate) head: Point,
+}
+
+/// The cells covered by a [`Selection`] once words and lines are expanded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SelectionRange {
+    pub(crate) start: Point,
+    pub(crate) end: Point,
+    pub(crate) block: bool,
+}
+
+impl Selection {
+    pub(crate) fn new(kind: SelectionKind, at: Point) -> Self {
+        Self {
+            kind,
+            anchor: at,
+            head: at,
+        }
+    }
+
+    /// Moves both ends by `lines`, used when rows scroll into the scrollback.
+    pub(crate) fn shift(&mut self, lines: isize) {
+        self.anchor.line += lines;
+        self.head.line += lines;
+    }
+
+    /// Expands the selection using `line` to look up the text of a row.
+    /// Returns `None` while a simple selection has not been dragged yet.
+    pub(crate) fn range<'a>(
+        &self,
+        line: impl Fn(isize) -> Option<&'a [char]>,
+    ) -> Option<SelectionRange> {
+        let mut start = self.anchor.min(self.head);
+        let mut end = self.anchor.max(self.head);
+
+        match self.kind {
+            SelectionKind::Simple if start == end => return None,
+            SelectionKind::Simple => {}
+            SelectionKind::Block => {
+                return Some(SelectionRange {
+                    start: Point {
+                        line: start.line,
+                        col: start.col.min(end.col),
+                    },
+                    end: Point {
+                        line: end.line,
+                        col: start.col.max(end.col),
+                    },
+                    block: true,
+                });
+            }
+            SelectionKind::Word => {
+                if let Some(text) = line(start.line).filter(|t| is_word_char(char_at(t, start.col))) {
+                    while start.col > 0 && is_word_char(char_at(text, start.col - 1)) {
+                        start.col -= 1;
+                    }
+                }
+                if let Some(text) = line(end.line).filter(|t| is_word_char(char_at(t, end.col))) {
+                    while end.col + 1 < text.len() && is_word_char(char_at(text, end.col + 1)) {
+                        end.col += 1;
+                    }
+                }
+            }
+            SelectionKind::Line => {
+                start.col = 0;
+                end.col = usize::MAX;
+            }
+        }
+
+        Some(SelectionRange {
+            start,
+            end,
+            block: false,
+        })
+    }
+}
+
+impl SelectionRange {
+    pub(crate) fn contains(&self, point: Point) -> bool {
+        if self.block {
+            (self.start.line..=self.end.line).contains(&point.line)
+                && (self.start.col..=self.end.col).contains(&point.col)
+        } else {
+            self.start <= point && point <= self.end
+        }
+    }
+
+    /// Whether any cell of `line` is selected.
+    pub(crate) fn contains_line(&self, line: isize) -> bool {
+        (self.start.line..=self.end.line).contains(&line)
+    }
+
+    /// Copies the selected text, one row per line with trailing blanks removed.
+    pub(crate) fn text<'a>(&self, line: impl Fn(isize) -> Option<&'a [char]>) -> String {
+        let mut text = String::new();
+
+        for l in self.start.line..=self.end.line {
+            let row = line(l).unwrap_or_default();
+
+            let (from, to) = if self.block {
+                (self.start.col, self.end.col)
+            } else {
+                (
+                    if l == self.start.line { self.start.col } else { 0 },
+                    if l == self.end.line { self.end.col } else { usize::MAX },
+                )
+            };
+
+            let row = &row[from.min(row.len())..to.saturating_add(1).min(row.len())];
+            let row: String = row.iter().map(|&c| if c == '\0' { ' ' } else { c }).collect();
+
+            if l != self.start.line {
+                text.push('\n');
+            }
+            text.push_str(row.trim_end());
+        }
+
+        text
+    }
+}
+
+fn char_at(text: &[char], col: usize) -> char {
+    text.get(col).copied().unwrap_or('\0')
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && c != '\0' && !"()[]{}<>'\"`,;|".contains(c)
+}
@@ -0,0 +1,24 @@
+#version 330 core
+
+uniform sampler2D text;
+// bottom-left corner and size of the scaled up text, in GL coordinates
+uniform vec2 textOrigin;
+uniform vec2 textSize;
+// lines drawn this frame when interlaced, 0 or 1, or -1 for all lines
+uniform int field;
+
+in vec2 uv;
+
+void main() {
+    if (field >= 0 && int(gl_FragCoord.y) % 2 != field) {
+        discard;
+    }
+
+    vec2 t = (uv * 2.0 - 1.0 - textOrigin) / textSize;
+    if (any(lessThan(t, vec2(0.0))) || any(greaterThanEqual(t, vec2(1.0)))) {
+        discard;
+    }
+
+    // every texel covers the same whole number of frame pixels
+    gl_FragColor = texelFetch(text, ivec2(t * vec2(textureSize(text, 0))), 0);
+}